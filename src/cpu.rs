@@ -0,0 +1,900 @@
+use std::ops::Range;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    error::Error,
+    expression::CustomFormula,
+    palette::{Palette, PALETTE_PERIOD},
+    trap::OrbitTrap,
+    viewport::Viewport,
+    Coloring, Formula, Fractal, Interior, TrapShape, MAX_POWER, MIN_POWER,
+};
+
+// Longest cycle looked for inside the set. Must match `MAX_PERIOD` in the
+// fragment shader.
+const MAX_PERIOD: i32 = 64;
+
+// Largest image drawn at once, as large as WebGL textures go on most devices.
+pub const MAX_SIZE: u32 = 16384;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Params {
+    pub viewport: Viewport,
+    pub iterations: i32,
+    pub fractal: Fractal,
+    pub c: [f32; 2],
+    pub formula: Formula,
+    // Only used by `Formula::Multibrot`.
+    pub power: f32,
+    // Only used by `Formula::Custom`.
+    pub custom: CustomFormula,
+    pub palette: Palette,
+    pub palette_speed: f32,
+    pub palette_offset: f32,
+    // Samples per pixel along each axis.
+    pub samples: u32,
+    // Whether samples are placed randomly within their cells of the grid.
+    pub jitter: bool,
+    pub coloring: Coloring,
+    pub interior: Interior,
+    pub trap: OrbitTrap,
+}
+
+// `z^n`, by repeated multiplication when `n` is a small positive integer, as
+// `Power()` in the fragment shader.
+pub fn power(z: [f32; 2], n: f32) -> [f32; 2] {
+    if n.fract() == 0. && (1. ..=64.).contains(&n) {
+        let mut w = z;
+        for _ in 1..n as i32 {
+            w = mul(w, z);
+        }
+        return w;
+    }
+    if z == [0., 0.] {
+        return z;
+    }
+    let angle = z[1].atan2(z[0]) * n;
+    scale([angle.cos(), angle.sin()], z[0].hypot(z[1]).powf(n))
+}
+
+// One iteration of the formula, as `Step()` in the fragment shader.
+pub fn step(z: [f32; 2], c: [f32; 2], params: &Params) -> [f32; 2] {
+    match params.formula {
+        Formula::Multibrot => return add(power(z, params.power), c),
+        Formula::Custom => return params.custom.eval(z, c),
+        _ => {}
+    }
+
+    let [re, im] = [z[0] * z[0] - z[1] * z[1], 2. * z[0] * z[1]];
+    let z2 = match params.formula {
+        Formula::BurningShip => [re, im.abs()],
+        Formula::Tricorn => [re, -im],
+        Formula::Celtic => [re.abs(), im],
+        Formula::Buffalo => [re.abs(), im.abs()],
+        _ => [re, im],
+    };
+    add(z2, c)
+}
+
+// Derivative of `step` with respect to `z`, times `dz`, as
+// `StepDerivative()` in the fragment shader.
+pub fn step_derivative(z: [f32; 2], c: [f32; 2], dz: [f32; 2], params: &Params) -> [f32; 2] {
+    match params.formula {
+        Formula::Multibrot => scale(mul(power(z, params.power - 1.), dz), params.power),
+        Formula::Custom => {
+            let length = dz[0].hypot(dz[1]);
+            if length == 0. {
+                return dz;
+            }
+            let h = scale(dz, 1e-3 / length);
+            let ahead = params.custom.eval(add(z, h), c);
+            let behind = params.custom.eval([z[0] - h[0], z[1] - h[1]], c);
+            scale([ahead[0] - behind[0], ahead[1] - behind[1]], length / 2e-3)
+        }
+        _ => scale(mul(z, dz), 2.),
+    }
+}
+
+// Degree of the formula, which smooth coloring depends on.
+pub fn degree(params: &Params) -> f32 {
+    match params.formula {
+        Formula::Multibrot => params.power,
+        Formula::Custom => params.custom.degree().unwrap_or(2.),
+        _ => 2.,
+    }
+}
+
+// Returns the last `z` and the iteration it escaped at, or 0 if it never did,
+// just like `Escape()` in the fragment shader.
+pub fn escape(z: [f32; 2], c: [f32; 2], params: &Params) -> ([f32; 2], i32) {
+    let mut z = z;
+    for i in 1..=params.iterations {
+        if z[0] * z[0] + z[1] * z[1] > 4.0 {
+            return (z, i);
+        }
+
+        z = step(z, c, params);
+    }
+    (z, 0)
+}
+
+// Same as `escape`, also iterating the derivative of `z` with respect to the
+// point, to which `dc` is added every iteration. Returns the length of the
+// derivative along with the last `z`, as `Escape()` does for distance
+// estimation.
+pub fn escape_with_derivative(
+    z: [f32; 2],
+    c: [f32; 2],
+    dc: f32,
+    params: &Params,
+) -> ([f32; 2], i32, f32) {
+    let mut z = z;
+    let mut dz = [1f32, 0.];
+    for i in 1..=params.iterations {
+        if z[0] * z[0] + z[1] * z[1] > 4.0 {
+            return (z, i, dz[0].hypot(dz[1]));
+        }
+
+        dz = add(step_derivative(z, c, dz, params), [dc, 0.]);
+        z = step(z, c, params);
+    }
+    (z, 0, 0.)
+}
+
+// Same as `escape`, also returning the distance the orbit came closest to
+// `trap` by before escaping.
+pub fn escape_with_trap(
+    z: [f32; 2],
+    c: [f32; 2],
+    trap: &OrbitTrap,
+    params: &Params,
+) -> ([f32; 2], i32, f32) {
+    let mut z = z;
+    let mut distance = f32::MAX;
+    for i in 1..=params.iterations {
+        if z[0] * z[0] + z[1] * z[1] > 4.0 {
+            return (z, i, distance);
+        }
+
+        distance = distance.min(trap.distance(z));
+        z = step(z, c, params);
+    }
+    (z, 0, distance)
+}
+
+// https://en.wikipedia.org/wiki/Plotting_algorithms_for_the_Mandelbrot_set
+pub fn smooth_iteration(i: i32, z: [f32; 2], degree: f32) -> f32 {
+    let log_zn = (z[0] * z[0] + z[1] * z[1]).ln() / 2.;
+    let nu = (log_zn / 2f32.ln()).log2() / degree.log2();
+    i as f32 + 1. - nu
+}
+
+pub fn color(i: i32, z: [f32; 2], params: &Params) -> [u8; 4] {
+    if i == 0 {
+        return [0, 0, 0, 255];
+    }
+
+    let it = smooth_iteration(i, z, degree(params));
+    let position = params.palette_offset + it * params.palette_speed / PALETTE_PERIOD;
+    let [r, g, b] = params.palette.color(position);
+    [r, g, b, 255]
+}
+
+fn mul(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] * b[0] - a[1] * b[1], a[0] * b[1] + a[1] * b[0]]
+}
+
+fn add(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+fn scale(a: [f32; 2], s: f32) -> [f32; 2] {
+    [a[0] * s, a[1] * s]
+}
+
+// Period of the cycle the orbit of a point inside the set has been drawn
+// into, with `z` the last point of that orbit, or 0 if none is found, as
+// `Period()` in the fragment shader.
+pub fn period(z: [f32; 2], c: [f32; 2], params: &Params) -> i32 {
+    let mut w = z;
+    for p in 1..=MAX_PERIOD {
+        w = step(w, c, params);
+        if (w[0] - z[0]).hypot(w[1] - z[1]) < 1e-4 {
+            return p;
+        }
+    }
+    0
+}
+
+// Shade of a point `c` inside the set by its distance to the boundary, as
+// `InteriorShade()` in the fragment shader.
+pub fn interior_shade(z: [f32; 2], c: [f32; 2], period: i32, pixel: f32, fractal: Fractal) -> f32 {
+    let mut z = z;
+    let mut dz = [1., 0.];
+    let mut dc = [0., 0.];
+    let mut dzdz = [0., 0.];
+    let mut dcdz = [0., 0.];
+    for _ in 0..period {
+        dcdz = scale(add(mul(z, dcdz), mul(dz, dc)), 2.);
+        dzdz = scale(add(mul(z, dzdz), mul(dz, dz)), 2.);
+        dz = scale(mul(z, dz), 2.);
+        dc = add(scale(mul(z, dc), 2.), [1., 0.]);
+        z = add(mul(z, z), c);
+    }
+    let attraction = 1. - dz[0] * dz[0] - dz[1] * dz[1];
+    if fractal == Fractal::Julia {
+        return attraction;
+    }
+
+    // dc / (1 - dz)
+    let w = [1. - dz[0], -dz[1]];
+    let norm = w[0] * w[0] + w[1] * w[1];
+    let ratio = [
+        (dc[0] * w[0] + dc[1] * w[1]) / norm,
+        (dc[1] * w[0] - dc[0] * w[1]) / norm,
+    ];
+    let derivative = add(dcdz, mul(dzdz, ratio));
+    let distance = attraction / derivative[0].hypot(derivative[1]);
+    distance / pixel / 4.
+}
+
+// Colors a point `c` inside the set, whose orbit ended at `z`, as
+// `InteriorValue()` and `Paint()` in the fragment shader.
+pub fn interior_color(z: [f32; 2], c: [f32; 2], pixel: f32, params: &Params) -> [u8; 4] {
+    let palette_color = |position: f32| {
+        let [r, g, b] = params
+            .palette
+            .color(params.palette_offset + position * params.palette_speed);
+        [r, g, b]
+    };
+    let period = match params.interior {
+        Interior::Black => return [0, 0, 0, 255],
+        Interior::Magnitude => {
+            let [r, g, b] = palette_color(z[0].hypot(z[1]) / 2.);
+            return [r, g, b, 255];
+        }
+        Interior::Period | Interior::Distance => period(z, c, params),
+    };
+    if period == 0 {
+        return [0, 0, 0, 255];
+    }
+
+    let mut color = palette_color(period as f32 / PALETTE_PERIOD);
+    // Distances are only estimated for z^2 + c.
+    if params.interior == Interior::Distance && params.formula == Formula::Quadratic {
+        let shade = interior_shade(z, c, period, pixel, params.fractal)
+            .clamp(0., 1.)
+            .sqrt();
+        color = color.map(|c| (c as f32 * shade).round() as u8);
+    }
+    let [r, g, b] = color;
+    [r, g, b, 255]
+}
+
+// Colors a point whose orbit escaped after coming within `distance` of the
+// trap, as `Sample()` does.
+pub fn trap_color(i: i32, z: [f32; 2], distance: f32, params: &Params) -> [u8; 4] {
+    let t = distance / params.trap.width;
+    let [r, g, b] = params
+        .palette
+        .color(params.palette_offset + t * params.palette_speed);
+    if params.trap.shape != TrapShape::Stalks {
+        return [r, g, b, 255];
+    }
+
+    // Pickover stalks blend into the smooth coloring away from the trap.
+    let smooth = color(i, z, params);
+    let t = t.clamp(0., 1.);
+    let [r, g, b] = [0, 1, 2].map(|k| {
+        let (a, b) = ([r, g, b][k] as f32, smooth[k] as f32);
+        (a + (b - a) * t).round() as u8
+    });
+    [r, g, b, 255]
+}
+
+// Complex coordinate of the point `(x, y)`, in pixels from the top left.
+pub fn pixel_to_complex(x: f64, y: f64, width: u32, height: u32, params: &Params) -> [f32; 2] {
+    params
+        .viewport
+        .to_complex(x, y, width as f64, height as f64)
+        .map(|v| v as f32)
+}
+
+pub fn sample(x: f64, y: f64, width: u32, height: u32, params: &Params) -> [u8; 4] {
+    let p = pixel_to_complex(x, y, width, height, params);
+    let (z, c, dc) = match params.fractal {
+        Fractal::Mandelbrot => (p, p, 1.),
+        Fractal::Julia => (p, params.c, 0.),
+    };
+    let pixel = 2. * params.viewport.half_width as f32 / width as f32;
+    match params.coloring {
+        Coloring::Smooth => {
+            let (z, i) = escape(z, c, params);
+            if i == 0 {
+                return interior_color(z, c, pixel, params);
+            }
+            return color(i, z, params);
+        }
+        Coloring::Trap => {
+            let (z, i, distance) = escape_with_trap(z, c, &params.trap, params);
+            if i == 0 {
+                return interior_color(z, c, pixel, params);
+            }
+            return trap_color(i, z, distance, params);
+        }
+        Coloring::Distance => {}
+    }
+
+    let (z, i, derivative) = escape_with_derivative(z, c, dc, params);
+    if i == 0 {
+        return interior_color(z, c, pixel, params);
+    }
+    let [r, g, b, a] = color(i, z, params);
+    // Distance to the boundary in pixels, darkening the color within a few
+    // of them as `Sample()` does.
+    let radius = z[0].hypot(z[1]);
+    let distance = radius * radius.ln() / derivative / pixel;
+    let shade = (distance / 4.).clamp(0., 1.).sqrt();
+    let [r, g, b] = [r, g, b].map(|c| (c as f32 * shade).round() as u8);
+    [r, g, b, a]
+}
+
+// https://nullprogram.com/blog/2018/07/31/
+pub fn hash(x: u32) -> u32 {
+    let mut x = x;
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+// Where sample `(i, j)` lies in the pixel at column `x` and row `y`, both
+// counted from the bottom left like `gl_FragCoord`, as placed by `main()` in
+// the fragment shader. A single sample stays at the center, jittered or not.
+pub fn sample_offset(x: u32, y: u32, i: u32, j: u32, params: &Params) -> [f32; 2] {
+    let offset = if params.jitter && params.samples > 1 {
+        let h = hash(x.wrapping_add(hash(y.wrapping_add(hash(j * params.samples + i)))));
+        [(h & 0xffff) as f32 / 65536., (h >> 16) as f32 / 65536.]
+    } else {
+        [0.5, 0.5]
+    };
+    [
+        (i as f32 + offset[0]) / params.samples as f32,
+        (j as f32 + offset[1]) / params.samples as f32,
+    ]
+}
+
+// Averages the samples of the pixel at column `x` and row `y`, counted from
+// the top.
+pub fn render_pixel(x: u32, y: u32, width: u32, height: u32, params: &Params) -> [u8; 4] {
+    let mut sum = [0.; 4];
+    for j in 0..params.samples {
+        for i in 0..params.samples {
+            let [dx, dy] = sample_offset(x, height - 1 - y, i, j, params);
+            let color = sample(
+                x as f64 + dx as f64,
+                (y + 1) as f64 - dy as f64,
+                width,
+                height,
+                params,
+            );
+            for (sum, c) in sum.iter_mut().zip(color) {
+                *sum += c as f32;
+            }
+        }
+    }
+    sum.map(|c| (c / (params.samples * params.samples) as f32).round() as u8)
+}
+
+// Fails unless an image of `width` by `height` is at most `MAX_SIZE` wide and
+// high, and not empty.
+pub fn check_size(width: u32, height: u32) -> Result<(), Error> {
+    if !((1..=MAX_SIZE).contains(&width) && (1..=MAX_SIZE).contains(&height)) {
+        return Err(Error::InvalidArgument(format!(
+            "image size must be between 1 and {MAX_SIZE}"
+        )));
+    }
+    Ok(())
+}
+
+// Renders into an RGBA buffer laid out row by row from the top, as expected
+// by `ImageData`.
+pub fn render(width: u32, height: u32, params: &Params) -> Vec<u8> {
+    render_rows(width, height, 0..height, params)
+}
+
+// Renders only the given rows of the image, counted from the top.
+pub fn render_rows(width: u32, height: u32, rows: Range<u32>, params: &Params) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(width as usize * rows.len() * 4);
+    for y in rows {
+        for x in 0..width {
+            pixels.extend_from_slice(&render_pixel(x, y, width, height, params));
+        }
+    }
+    pixels
+}
+
+// Colors with the default palette and a sample per pixel. `power` is only used
+// by `Formula::Multibrot`, and `expression` by `Formula::Custom`.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn render_cpu(
+    width: u32,
+    height: u32,
+    viewport: &Viewport,
+    iterations: i32,
+    fractal: Fractal,
+    c: &[f32],
+    formula: Formula,
+    power: f32,
+    expression: &str,
+) -> Result<Vec<u8>, Error> {
+    check_size(width, height)?;
+    if !(power.is_finite() && (MIN_POWER..=MAX_POWER).contains(&power)) {
+        return Err(Error::InvalidArgument(format!(
+            "power must be between {MIN_POWER} and {MAX_POWER}"
+        )));
+    }
+    let custom = match formula {
+        Formula::Custom => CustomFormula::parse(expression).map_err(Error::InvalidArgument)?,
+        _ => CustomFormula::default(),
+    };
+
+    let params = Params {
+        viewport: viewport.clone(),
+        iterations,
+        fractal,
+        c: c.try_into()
+            .map_err(|_| Error::InvalidArgument("expected two components".to_string()))?,
+        formula,
+        power,
+        custom,
+        palette: Palette::default(),
+        palette_speed: 1.,
+        palette_offset: 0.,
+        samples: 1,
+        jitter: false,
+        coloring: Coloring::Smooth,
+        interior: Interior::Black,
+        trap: OrbitTrap::default(),
+    };
+    Ok(render(width, height, &params))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn params() -> Params {
+        Params {
+            viewport: Viewport::new(-0.7, 0., 1.8, 4., 3.).unwrap(),
+            iterations: 100,
+            fractal: Fractal::Mandelbrot,
+            c: [0., 0.],
+            formula: Formula::Quadratic,
+            power: 2.,
+            custom: CustomFormula::default(),
+            palette: Palette::default(),
+            palette_speed: 1.,
+            palette_offset: 0.,
+            samples: 1,
+            jitter: false,
+            coloring: Coloring::Smooth,
+            interior: Interior::Black,
+            trap: OrbitTrap::default(),
+        }
+    }
+
+    #[test]
+    fn origin_never_escapes() {
+        assert_eq!(escape([0., 0.], [0., 0.], &params()), ([0., 0.], 0));
+    }
+
+    #[test]
+    fn one_escapes_at_third_iteration() {
+        // 1, 2, 5: |5|^2 is the first past the bailout.
+        assert_eq!(escape([1., 0.], [1., 0.], &params()), ([5., 0.], 3));
+    }
+
+    #[test]
+    fn smooth_iteration_matches_integer_where_log_is_whole() {
+        // |z| = 4 gives log2(log2 |z|) = 1, one iteration short of i + 1.
+        assert!((smooth_iteration(3, [4., 0.], 2.) - 3.).abs() < 1e-6);
+        assert!((smooth_iteration(3, [16., 0.], 2.) - 2.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn formulas_escape_at_known_iterations() {
+        // Counted with each formula written out in f64: (|x| + i|y|)^2 + c for
+        // the Burning Ship, conj(z)^2 + c for the Tricorn, and z^2 + c with the
+        // absolute value of its real part, or of both parts, for Celtic and
+        // Buffalo.
+        let formulas = [
+            Formula::Quadratic,
+            Formula::BurningShip,
+            Formula::Tricorn,
+            Formula::Celtic,
+            Formula::Buffalo,
+        ];
+        for (c, counts) in [
+            ([-0.5, 0.5], [0, 4, 4, 0, 4]),
+            ([0.25, 0.5], [0, 9, 0, 4, 4]),
+            ([0.5, 0.5], [5, 4, 7, 4, 4]),
+            ([-0.2, -1.], [7, 0, 3, 4, 0]),
+            ([-1.5, -0.5], [3, 3, 2, 5, 0]),
+        ] {
+            for (formula, count) in formulas.into_iter().zip(counts) {
+                let params = Params {
+                    formula,
+                    ..params()
+                };
+                assert_eq!(escape(c, c, &params).1, count, "{formula:?} at {c:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn formulas_agree_with_their_equivalents() {
+        let count = |formula, power, source: &str, c| {
+            let params = Params {
+                formula,
+                power,
+                custom: match formula {
+                    Formula::Custom => CustomFormula::parse(source).unwrap(),
+                    _ => CustomFormula::default(),
+                },
+                ..params()
+            };
+            escape(c, c, &params).1
+        };
+        for y in -4..=4 {
+            for x in -8..=4 {
+                let c = [x as f32 / 4., y as f32 / 4.];
+                let quadratic = count(Formula::Quadratic, 2., "", c);
+                assert_eq!(count(Formula::Multibrot, 2., "", c), quadratic, "{c:?}");
+                assert_eq!(count(Formula::Custom, 2., "z^2 + c", c), quadratic, "{c:?}");
+                assert_eq!(
+                    count(Formula::Multibrot, 3., "", c),
+                    count(Formula::Custom, 2., "z*z*z + c", c),
+                    "{c:?}"
+                );
+                assert_eq!(
+                    count(Formula::Tricorn, 2., "", c),
+                    count(Formula::Custom, 2., "conj(z)^2 + c", c),
+                    "{c:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn render_cpu_draws_the_formula() {
+        let viewport = Viewport::new(-0.5, 0., 1.5, 1., 1.).unwrap();
+        let draw = |formula, power, expression| {
+            render_cpu(
+                8,
+                8,
+                &viewport,
+                50,
+                Fractal::Mandelbrot,
+                &[0., 0.],
+                formula,
+                power,
+                expression,
+            )
+        };
+        let quadratic = draw(Formula::Quadratic, 2., "").unwrap();
+        assert_eq!(draw(Formula::Custom, 2., "z^2 + c").unwrap(), quadratic);
+        assert_ne!(draw(Formula::BurningShip, 2., "").unwrap(), quadratic);
+        assert_ne!(draw(Formula::Multibrot, 3., "").unwrap(), quadratic);
+        assert!(draw(Formula::Multibrot, 100., "").is_err());
+        assert!(draw(Formula::Custom, 2., "z^").is_err());
+    }
+
+    #[test]
+    fn render_cpu_bounds_the_size() {
+        let viewport = Viewport::new(-0.5, 0., 1.5, 1., 1.).unwrap();
+        let draw = |width, height| {
+            render_cpu(
+                width,
+                height,
+                &viewport,
+                1,
+                Fractal::Mandelbrot,
+                &[0., 0.],
+                Formula::Quadratic,
+                2.,
+                "",
+            )
+        };
+        assert_eq!(draw(1, 1).unwrap().len(), 4);
+        for (width, height) in [(0, 1), (1, 0), (MAX_SIZE + 1, 1), (1, MAX_SIZE + 1)] {
+            assert!(draw(width, height).is_err(), "{width} by {height}");
+        }
+    }
+
+    #[test]
+    fn render_fills_rgba_rows() {
+        assert_eq!(render(4, 3, &params()).len(), 4 * 3 * 4);
+        assert_eq!(render_rows(4, 3, 1..3, &params()).len(), 4 * 2 * 4);
+    }
+
+    #[test]
+    fn render_colors_interior_black() {
+        let mut params = params();
+        params.viewport = Viewport::new(0., 0., 1e-3, 1., 1.).unwrap();
+        assert_eq!(render(1, 1, &params), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn sample_offsets_stay_in_their_cells() {
+        for jitter in [false, true] {
+            let params = Params {
+                samples: 3,
+                jitter,
+                ..params()
+            };
+            for (x, y) in [(0, 0), (1, 0), (17, 250), (u32::MAX, u32::MAX)] {
+                for j in 0..3 {
+                    for i in 0..3 {
+                        let [dx, dy] = sample_offset(x, y, i, j, &params);
+                        assert!((i as f32 / 3. ..(i + 1) as f32 / 3.).contains(&dx), "{dx}");
+                        assert!((j as f32 / 3. ..(j + 1) as f32 / 3.).contains(&dy), "{dy}");
+                        // The same for every frame, so that images do not
+                        // flicker.
+                        assert_eq!(sample_offset(x, y, i, j, &params), [dx, dy]);
+                        if !jitter {
+                            assert_eq!([dx, dy], [(i as f32 + 0.5) / 3., (j as f32 + 0.5) / 3.]);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Jittered pixels place their samples differently.
+        let params = Params {
+            samples: 2,
+            jitter: true,
+            ..params()
+        };
+        let offsets: Vec<_> = (0..16)
+            .map(|x| sample_offset(x, 0, 0, 0, &params))
+            .collect();
+        assert!(offsets[1..].iter().all(|&offset| offset != offsets[0]));
+    }
+
+    #[test]
+    fn one_sample_lies_at_the_pixel_center() {
+        for jitter in [false, true] {
+            let params = Params { jitter, ..params() };
+            for y in 0..12 {
+                for x in 0..16 {
+                    assert_eq!(
+                        render_pixel(x, y, 16, 12, &params),
+                        sample(x as f64 + 0.5, y as f64 + 0.5, 16, 12, &params),
+                        "({x}, {y})"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_averaged_across_edges() {
+        let params = Params {
+            samples: 2,
+            ..params()
+        };
+        let samples = |x: u32, y: u32| {
+            [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]
+                .map(|(dx, dy)| sample(x as f64 + dx, y as f64 + dy, 16, 12, &params))
+        };
+        // A pixel of the middle row with samples both inside and outside.
+        let samples = (0..16).map(|x| (x, samples(x, 6))).find(|(_, samples)| {
+            samples.contains(&[0, 0, 0, 255]) && samples.iter().any(|&s| s != [0, 0, 0, 255])
+        });
+        let (x, samples) = samples.unwrap();
+
+        let average = [0, 1, 2, 3]
+            .map(|k| (samples.iter().map(|s| s[k] as f32).sum::<f32>() / 4.).round() as u8);
+        assert_eq!(render_pixel(x, 6, 16, 12, &params), average);
+        assert!(!samples.contains(&average));
+    }
+
+    #[test]
+    fn derivative_matches_finite_differences() {
+        // The last `z` as a function of the starting point, with the number of
+        // steps it took to escape from `start` held fixed.
+        let last_z = |fractal, start: [f32; 2], steps: i32, params: &Params| {
+            let (mut z, c) = match fractal {
+                Fractal::Mandelbrot => (start, start),
+                Fractal::Julia => (start, params.c),
+            };
+            for _ in 0..steps {
+                z = step(z, c, params);
+            }
+            z
+        };
+        for (fractal, dc) in [(Fractal::Mandelbrot, 1.), (Fractal::Julia, 0.)] {
+            let params = Params {
+                fractal,
+                c: [-0.8, 0.156],
+                ..params()
+            };
+            for start in [[0.5, 0.5], [-0.5, 0.7], [0.1, 1.], [-1.5, 0.6]] {
+                let c = match fractal {
+                    Fractal::Mandelbrot => start,
+                    Fractal::Julia => params.c,
+                };
+                let (z, i, derivative) = escape_with_derivative(start, c, dc, &params);
+                assert!(i > 1, "{fractal:?} at {start:?}");
+                assert_eq!(last_z(fractal, start, i - 1, &params), z);
+
+                let h = 1e-3;
+                let forward = last_z(fractal, [start[0] + h, start[1]], i - 1, &params);
+                let backward = last_z(fractal, [start[0] - h, start[1]], i - 1, &params);
+                let difference =
+                    (forward[0] - backward[0]).hypot(forward[1] - backward[1]) / (2. * h);
+                assert!(
+                    (difference / derivative - 1.).abs() < 1e-2,
+                    "{fractal:?} at {start:?}: {difference} != {derivative}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn distance_darkens_near_the_boundary() {
+        let draw = |c: [f32; 2], coloring| {
+            let params = Params {
+                viewport: Viewport::new(c[0] as f64, c[1] as f64, 0.5, 2., 2.).unwrap(),
+                coloring,
+                ..params()
+            };
+            sample(1., 1., 2, 2, &params)
+        };
+        // Far enough that the shade is whole.
+        let far = [3., 3.];
+        assert_eq!(draw(far, Coloring::Distance), draw(far, Coloring::Smooth));
+        // A hundredth outside the cusp, under a pixel half as wide as the view.
+        let near = [0.26, 0.];
+        let (shaded, smooth) = (draw(near, Coloring::Distance), draw(near, Coloring::Smooth));
+        assert!(
+            shaded[..3].iter().zip(smooth).all(|(&a, b)| a <= b),
+            "{shaded:?}"
+        );
+        assert!(shaded[..3].iter().sum::<u8>() < smooth[..3].iter().sum::<u8>() / 2);
+        assert_eq!(shaded[3], 255);
+    }
+
+    #[test]
+    fn period_finds_the_cycle_of_the_orbit() {
+        let params = Params {
+            iterations: 1000,
+            ..params()
+        };
+        for (c, expected) in [
+            ([0., 0.], 1),
+            ([-0.2, 0.1], 1),
+            ([-1., 0.], 2),
+            ([-1.1, 0.1], 2),
+            ([-0.12, 0.75], 3),
+            ([-1.31, 0.], 4),
+        ] {
+            let (z, i) = escape(c, c, &params);
+            assert_eq!(i, 0, "{c:?}");
+            assert_eq!(period(z, c, &params), expected, "{c:?}");
+        }
+    }
+
+    #[test]
+    fn interior_colors_by_mode() {
+        // Inside the period 2 bulb, a tenth or so from its edge.
+        let c = [-1.1, 0.];
+        let (z, _) = escape(c, c, &params());
+        let color = |interior, pixel| {
+            let params = Params {
+                interior,
+                palette_offset: 0.2,
+                ..params()
+            };
+            interior_color(z, c, pixel, &params)
+        };
+        let palette = |position: f32| {
+            let [r, g, b] = Palette::default().color(0.2 + position);
+            [r, g, b, 255]
+        };
+
+        assert_eq!(color(Interior::Black, 1e-3), [0, 0, 0, 255]);
+        assert_eq!(
+            color(Interior::Magnitude, 1e-3),
+            palette(z[0].hypot(z[1]) / 2.)
+        );
+        assert_eq!(period(z, c, &params()), 2);
+        assert_eq!(color(Interior::Period, 1e-3), palette(2. / PALETTE_PERIOD));
+        // Whole far from the boundary, in pixels, and darker near it.
+        assert_eq!(
+            color(Interior::Distance, 1e-6),
+            color(Interior::Period, 1e-6)
+        );
+        let shaded = color(Interior::Distance, 0.1);
+        let full = color(Interior::Period, 0.1);
+        assert!(
+            shaded[..3].iter().zip(full).all(|(&a, b)| a <= b),
+            "{shaded:?}"
+        );
+        assert_ne!(shaded, full);
+    }
+
+    #[test]
+    fn trap_distance_is_the_closest_the_orbit_came() {
+        let trap = |shape| OrbitTrap {
+            shape,
+            radius: 2.,
+            ..OrbitTrap::default()
+        };
+        // 1 and 2 before escaping at 5, which is past the bailout and left out.
+        for (shape, distance) in [
+            (TrapShape::Point, 1.),
+            (TrapShape::Line, 0.),
+            (TrapShape::Circle, 0.),
+            (TrapShape::Cross, 0.),
+        ] {
+            assert_eq!(
+                escape_with_trap([1., 0.], [1., 0.], &trap(shape), &params()),
+                ([5., 0.], 3, distance),
+                "{shape:?}"
+            );
+        }
+        // 0.5 + 0.5i first, and then 0.5 + i.
+        let (_, i, distance) =
+            escape_with_trap([0.5, 0.5], [0.5, 0.5], &trap(TrapShape::Point), &params());
+        assert_eq!(i, 5);
+        assert!((distance - 0.5f32.hypot(0.5)).abs() < 1e-6, "{distance}");
+        // Points inside the set keep the closest their whole orbit came.
+        assert_eq!(
+            escape_with_trap([-1., 0.], [-1., 0.], &trap(TrapShape::Point), &params()),
+            ([-1., 0.], 0, 0.)
+        );
+    }
+
+    #[test]
+    fn trap_colors_by_distance() {
+        let params = |shape| Params {
+            palette_offset: 0.2,
+            palette_speed: 0.5,
+            trap: OrbitTrap {
+                shape,
+                width: 0.25,
+                ..OrbitTrap::default()
+            },
+            ..params()
+        };
+        let (z, i) = escape([0.5, 0.5], [0.5, 0.5], &params(TrapShape::Point));
+        let palette = |position: f32| {
+            let [r, g, b] = Palette::default().color(position);
+            [r, g, b, 255]
+        };
+        for shape in [
+            TrapShape::Point,
+            TrapShape::Line,
+            TrapShape::Circle,
+            TrapShape::Cross,
+        ] {
+            // Half a width from the trap.
+            assert_eq!(
+                trap_color(i, z, 0.125, &params(shape)),
+                palette(0.2 + 0.5 * 0.5),
+                "{shape:?}"
+            );
+        }
+        // Stalks fade into the smooth coloring a width away from the trap.
+        let stalks = params(TrapShape::Stalks);
+        assert_eq!(trap_color(i, z, 0., &stalks), palette(0.2));
+        assert_eq!(trap_color(i, z, 0.25, &stalks), color(i, z, &stalks));
+        assert_eq!(trap_color(i, z, 10., &stalks), color(i, z, &stalks));
+    }
+}
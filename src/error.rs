@@ -0,0 +1,199 @@
+use std::fmt;
+
+use wasm_bindgen::{JsCast, JsValue};
+
+// Why the explorer failed. Thrown to JavaScript as an `Error` whose `kind` is
+// one of the strings of `kind()`, so that the page can tell failures apart,
+// for example to say that the browser cannot draw at all.
+#[derive(Clone, Debug)]
+pub enum Error {
+    NoWindow,
+    // The window has no document, or the document no body.
+    NoDocument,
+    // The window reported what it cannot, such as a size that is not a number.
+    Window(String),
+    // The canvas gave no context of the kind asked for, or the WebGL context
+    // failed to make a shader, program, texture, buffer or framebuffer.
+    Context(String),
+    ShaderCompile {
+        // The compiler's log, with the GLSL of each line it names.
+        log: String,
+        // The lines of the shader the log names, from 1.
+        lines: Vec<u32>,
+    },
+    Link(String),
+    // The name of a uniform every program has, missing from one.
+    MissingUniform(String),
+    // An argument out of range, or text that does not parse.
+    InvalidArgument(String),
+    Export(String),
+    // An exception thrown by a browser API.
+    Js(JsValue),
+}
+
+impl Error {
+    // Picks the line numbers out of a log of entries such as
+    // `ERROR: 0:42: 'x' : undeclared identifier`, and quotes those lines of
+    // `source` after them.
+    pub fn shader_compile(source: &str, log: &str) -> Error {
+        let source_lines: Vec<_> = source.lines().collect();
+        let mut annotated = String::new();
+        let mut lines = Vec::new();
+        for entry in log.lines() {
+            annotated.push_str(entry);
+            annotated.push('\n');
+            let Some(line) = line_number(entry) else {
+                continue;
+            };
+            if let Some(text) = source_lines.get(line as usize - 1) {
+                annotated.push_str(&format!("    {}\n", text.trim()));
+            }
+            if !lines.contains(&line) {
+                lines.push(line);
+            }
+        }
+
+        Error::ShaderCompile {
+            log: annotated.trim_end().to_string(),
+            lines,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::NoWindow => "no_window",
+            Error::NoDocument => "no_document",
+            Error::Window(_) => "window",
+            Error::Context(_) => "context",
+            Error::ShaderCompile { .. } => "shader_compile",
+            Error::Link(_) => "link",
+            Error::MissingUniform(_) => "missing_uniform",
+            Error::InvalidArgument(_) => "invalid_argument",
+            Error::Export(_) => "export",
+            Error::Js(_) => "js",
+        }
+    }
+}
+
+// The line of a log entry `ERROR: <source>:<line>: ...`, if it names one.
+fn line_number(entry: &str) -> Option<u32> {
+    let rest = entry
+        .strip_prefix("ERROR: ")
+        .or_else(|| entry.strip_prefix("WARNING: "))?;
+    let mut parts = rest.splitn(3, ':');
+    parts.next()?.trim().parse::<u32>().ok()?;
+    parts.next()?.trim().parse().ok().filter(|&line| line > 0)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoWindow => write!(f, "no window exists"),
+            Error::NoDocument => write!(f, "no document with a body exists"),
+            Error::Window(message) => write!(f, "{message}"),
+            Error::Context(message) => write!(f, "{message}"),
+            Error::ShaderCompile { log, .. } => write!(f, "failed to compile a shader:\n{log}"),
+            Error::Link(log) => write!(f, "failed to link the shaders: {log}"),
+            Error::MissingUniform(name) => write!(f, "the shader has no uniform `{name}`"),
+            Error::InvalidArgument(message) => write!(f, "{message}"),
+            Error::Export(message) => write!(f, "failed to export the image: {message}"),
+            Error::Js(value) => match value.as_string() {
+                Some(message) => write!(f, "{message}"),
+                None => write!(f, "{value:?}"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<JsValue> for Error {
+    fn from(value: JsValue) -> Error {
+        Error::Js(value)
+    }
+}
+
+impl From<Error> for JsValue {
+    fn from(error: Error) -> JsValue {
+        let kind = error.kind();
+        let js_error = match &error {
+            Error::Js(value) => value
+                .clone()
+                .dyn_into::<js_sys::Error>()
+                .unwrap_or_else(|_| js_sys::Error::new(&error.to_string())),
+            _ => js_sys::Error::new(&error.to_string()),
+        };
+        // Setting properties of an `Error` cannot fail.
+        let _ = js_sys::Reflect::set(&js_error, &"kind".into(), &kind.into());
+        if let Error::ShaderCompile { lines, .. } = &error {
+            let lines: js_sys::Array = lines.iter().map(|&line| JsValue::from(line)).collect();
+            let _ = js_sys::Reflect::set(&js_error, &"lines".into(), &lines);
+        }
+        js_error.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_line_of_an_entry() {
+        assert_eq!(
+            line_number("ERROR: 0:42: 'x' : undeclared identifier"),
+            Some(42)
+        );
+        assert_eq!(
+            line_number("WARNING: 1:7: extension not supported"),
+            Some(7)
+        );
+        // Other compilers' formats, and entries with no line.
+        for entry in [
+            "0(42) : error C1008: undefined variable \"x\"",
+            "ERROR: 42: 'x' : undeclared identifier",
+            "ERROR: 0:0: compilation terminated",
+            "ERROR: 0:-3: 'x' : undeclared identifier",
+            "ERROR: a:42: 'x' : undeclared identifier",
+            "ERROR: 2 compilation errors.  No code generated.",
+            "error: 0:42: 'x' : undeclared identifier",
+            "",
+        ] {
+            assert_eq!(line_number(entry), None, "{entry}");
+        }
+    }
+
+    #[test]
+    fn quotes_the_lines_the_log_names() {
+        let source =
+            "#version 300 es\nprecision highp float;\n  float x = y;\nvoid main() {\n\tz = x;\n}";
+        let log = "ERROR: 0:3: 'y' : undeclared identifier\n\
+                   ERROR: 0:5: 'z' : undeclared identifier\n\
+                   ERROR: 0:3: '=' : cannot convert\n\
+                   ERROR: 0:99: '' : past the end\n\
+                   ERROR: 4 compilation errors.  No code generated.\n";
+        let Error::ShaderCompile { log, lines } = Error::shader_compile(source, log) else {
+            panic!("not a compile error");
+        };
+        assert_eq!(lines, [3, 5, 99]);
+        assert_eq!(
+            log,
+            "ERROR: 0:3: 'y' : undeclared identifier\n    float x = y;\n\
+             ERROR: 0:5: 'z' : undeclared identifier\n    z = x;\n\
+             ERROR: 0:3: '=' : cannot convert\n    float x = y;\n\
+             ERROR: 0:99: '' : past the end\n\
+             ERROR: 4 compilation errors.  No code generated."
+        );
+    }
+
+    #[test]
+    fn keeps_logs_it_cannot_read() {
+        let log = "0(3) : error C1008: undefined variable \"y\"\n";
+        let error = Error::shader_compile("void main() {}", log);
+        let Error::ShaderCompile { log, lines } = &error else {
+            panic!("not a compile error");
+        };
+        assert_eq!(log, "0(3) : error C1008: undefined variable \"y\"");
+        assert!(lines.is_empty());
+        assert_eq!(error.kind(), "shader_compile");
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{
+    cpu::{self, Params},
+    error::Error,
+};
+
+// Encodes an RGBA buffer laid out row by row from the top.
+pub fn encode_png(width: u32, height: u32, pixels: &[u8]) -> Result<Vec<u8>, png::EncodingError> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    writer.finish()?;
+    Ok(bytes)
+}
+
+// Renders the view with the CPU renderer, so images can be made without a GPU.
+pub fn render_png(width: u32, height: u32, params: &Params) -> Result<Vec<u8>, Error> {
+    cpu::check_size(width, height)?;
+    let mut params = params.clone();
+    params.viewport.resize(width as f64, height as f64);
+    encode_png(width, height, &cpu::render(width, height, &params))
+        .map_err(|err| Error::Export(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::params;
+
+    #[test]
+    fn png_holds_the_rendered_pixels() {
+        let (width, height) = (8, 6);
+        let png = render_png(width, height, &params()).unwrap();
+
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (width, height));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!(info.bit_depth, png::BitDepth::Eight);
+        // The view already has the aspect ratio of the image.
+        assert_eq!(pixels, cpu::render(width, height, &params()));
+    }
+
+    #[test]
+    fn rejects_buffers_of_the_wrong_size() {
+        assert!(encode_png(2, 2, &[0; 15]).is_err());
+    }
+
+    #[test]
+    fn rejects_images_out_of_range() {
+        for (width, height) in [(0, 6), (8, 0), (cpu::MAX_SIZE + 1, 6)] {
+            assert!(render_png(width, height, &params()).is_err());
+        }
+    }
+}
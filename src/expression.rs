@@ -0,0 +1,892 @@
+use crate::cpu;
+
+// Formulas typed by users, in a small language of complex expressions in `z`
+// and `c`:
+//
+//     z^3 + sin(c)*z + c
+//
+// with numbers, `i`, `pi`, `+ - * / ^`, parentheses, and the functions `exp`,
+// `log`, `sin`, `cos`, `conj` and `abs`. They are compiled into the body of
+// `Step()` in the fragment shader, and interpreted by the CPU renderer.
+
+// Longest formula accepted, which keeps the shader quick to compile.
+pub const MAX_LENGTH: usize = 256;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomFormula {
+    source: String,
+    root: Typed,
+}
+
+impl CustomFormula {
+    pub fn parse(source: &str) -> Result<CustomFormula, String> {
+        if source.chars().count() > MAX_LENGTH {
+            return Err(format!("formulas are limited to {MAX_LENGTH} characters"));
+        }
+
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, next: 0 };
+        let syntax = parser.expression()?;
+        if let Some(&(column, ref token)) = parser.tokens.get(parser.next) {
+            return Err(format!("column {column}: unexpected {token}"));
+        }
+
+        Ok(CustomFormula {
+            source: source.to_string(),
+            root: check(syntax)?,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // GLSL expression of the formula as a `vec2`, in terms of `z` and `c`.
+    pub fn glsl(&self) -> String {
+        complex(&self.root)
+    }
+
+    // Evaluates the formula as the generated GLSL does, in single precision.
+    pub fn eval(&self, z: [f32; 2], c: [f32; 2]) -> [f32; 2] {
+        eval(&self.root, z, c)
+    }
+
+    // Power of `z` the formula grows as, which smooth coloring depends on, if
+    // it grows as a power above 1. Formulas such as `exp(z)` or `z + c` have
+    // none, and are colored as if they were quadratic.
+    pub fn degree(&self) -> Option<f32> {
+        degree(&self.root).filter(|&degree| degree > 1.)
+    }
+}
+
+impl Default for CustomFormula {
+    fn default() -> CustomFormula {
+        CustomFormula::parse("z^2 + c").unwrap()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(x) => write!(f, "number `{x}`"),
+            Token::Name(name) => write!(f, "`{name}`"),
+            Token::Symbol(symbol) => write!(f, "`{symbol}`"),
+        }
+    }
+}
+
+// Splits the source into tokens, each with the column it starts at, counted
+// from 1.
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, ch)) = chars.peek() {
+        let column = source[..start].chars().count() + 1;
+        if ch.is_whitespace() {
+            chars.next();
+        } else if ch.is_ascii_digit() || ch == '.' {
+            let mut end = start;
+            while let Some(&(i, ch)) = chars.peek() {
+                // Exponents such as `1e-3` take a sign after the `e`.
+                let sign = (ch == '-' || ch == '+') && source[..i].ends_with(['e', 'E']);
+                if !(ch.is_ascii_alphanumeric() || ch == '.' || sign) {
+                    break;
+                }
+                end = i + ch.len_utf8();
+                chars.next();
+            }
+            let text = &source[start..end];
+            let number = text
+                .parse::<f64>()
+                .map_err(|_| format!("column {column}: invalid number `{text}`"))?;
+            tokens.push((column, Token::Number(number)));
+        } else if ch.is_alphabetic() || ch == '_' {
+            let mut end = start;
+            while let Some(&(i, ch)) = chars.peek() {
+                if !(ch.is_alphanumeric() || ch == '_') {
+                    break;
+                }
+                end = i + ch.len_utf8();
+                chars.next();
+            }
+            tokens.push((column, Token::Name(source[start..end].to_string())));
+        } else if "+-*/^(),".contains(ch) {
+            tokens.push((column, Token::Symbol(ch)));
+            chars.next();
+        } else {
+            return Err(format!("column {column}: unexpected `{ch}`"));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+// The formula as parsed, with names still to be resolved.
+#[derive(Clone, Debug, PartialEq)]
+enum Syntax {
+    Number(f64),
+    // The column it was found at, for errors.
+    Name(usize, String),
+    Negate(Box<Syntax>),
+    Binary(Operator, Box<Syntax>, Box<Syntax>),
+    Call(usize, String, Vec<Syntax>),
+}
+
+// Recursive descent, from the loosest binding operators to the tightest:
+//
+//     expression = term (("+" | "-") term)*
+//     term = unary (("*" | "/") unary)*
+//     unary = "-" unary | power
+//     power = atom ("^" unary)?
+//     atom = number | name | name "(" arguments ")" | "(" expression ")"
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        if self.eat(symbol) {
+            return Ok(());
+        }
+        Err(match self.tokens.get(self.next) {
+            Some((column, token)) => format!("column {column}: expected `{symbol}`, found {token}"),
+            None => format!("expected `{symbol}` at the end"),
+        })
+    }
+
+    fn expression(&mut self) -> Result<Syntax, String> {
+        let mut left = self.term()?;
+        loop {
+            let operator = if self.eat('+') {
+                Operator::Add
+            } else if self.eat('-') {
+                Operator::Subtract
+            } else {
+                return Ok(left);
+            };
+            left = Syntax::Binary(operator, Box::new(left), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Syntax, String> {
+        let mut left = self.unary()?;
+        loop {
+            let operator = if self.eat('*') {
+                Operator::Multiply
+            } else if self.eat('/') {
+                Operator::Divide
+            } else {
+                return Ok(left);
+            };
+            left = Syntax::Binary(operator, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Syntax, String> {
+        if self.eat('-') {
+            return Ok(Syntax::Negate(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    // `^` binds tighter than a leading minus on its left, so `-z^2` is
+    // `-(z^2)`, and is right associative.
+    fn power(&mut self) -> Result<Syntax, String> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            let exponent = self.unary()?;
+            return Ok(Syntax::Binary(
+                Operator::Power,
+                Box::new(base),
+                Box::new(exponent),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Syntax, String> {
+        let Some((column, token)) = self.tokens.get(self.next).cloned() else {
+            return Err("unexpected end of the formula".to_string());
+        };
+        self.next += 1;
+        match token {
+            Token::Number(x) => Ok(Syntax::Number(x)),
+            Token::Name(name) => {
+                if !self.eat('(') {
+                    return Ok(Syntax::Name(column, name));
+                }
+                let mut arguments = vec![self.expression()?];
+                while self.eat(',') {
+                    arguments.push(self.expression()?);
+                }
+                self.expect(')')?;
+                Ok(Syntax::Call(column, name, arguments))
+            }
+            Token::Symbol('(') => {
+                let inner = self.expression()?;
+                self.expect(')')?;
+                Ok(inner)
+            }
+            token => Err(format!("column {column}: unexpected {token}")),
+        }
+    }
+}
+
+// Real values stay floats in the shader, and only become complex when they
+// meet a complex one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Type {
+    Real,
+    Complex,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Function {
+    Exp,
+    Log,
+    Sin,
+    Cos,
+    Conj,
+    Abs,
+}
+
+// The formula with names resolved and the type of every node known.
+#[derive(Clone, Debug, PartialEq)]
+struct Typed {
+    ty: Type,
+    node: Node,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Real(f32),
+    I,
+    Z,
+    C,
+    Negate(Box<Typed>),
+    Binary(Operator, Box<Typed>, Box<Typed>),
+    Call(Function, Box<Typed>),
+}
+
+fn check(syntax: Syntax) -> Result<Typed, String> {
+    let typed = |ty, node| Ok(Typed { ty, node });
+    match syntax {
+        Syntax::Number(x) => {
+            if !(x as f32).is_finite() {
+                return Err(format!("{x} is too large for a float"));
+            }
+            typed(Type::Real, Node::Real(x as f32))
+        }
+        Syntax::Name(column, name) => match name.as_str() {
+            "z" => typed(Type::Complex, Node::Z),
+            "c" => typed(Type::Complex, Node::C),
+            "i" => typed(Type::Complex, Node::I),
+            "pi" => typed(Type::Real, Node::Real(std::f32::consts::PI)),
+            _ => Err(format!("column {column}: unknown variable `{name}`")),
+        },
+        Syntax::Negate(operand) => {
+            let operand = check(*operand)?;
+            // Folded so that `z^-2` still has a constant exponent.
+            if let Node::Real(x) = operand.node {
+                return typed(Type::Real, Node::Real(-x));
+            }
+            typed(operand.ty, Node::Negate(Box::new(operand)))
+        }
+        Syntax::Binary(operator, left, right) => {
+            let left = check(*left)?;
+            let right = check(*right)?;
+            // Real powers can be complex, as with `(-1)^0.5`.
+            let ty = if operator == Operator::Power {
+                Type::Complex
+            } else if left.ty == Type::Real && right.ty == Type::Real {
+                Type::Real
+            } else {
+                Type::Complex
+            };
+            typed(ty, Node::Binary(operator, Box::new(left), Box::new(right)))
+        }
+        Syntax::Call(column, name, arguments) => {
+            let function = match name.as_str() {
+                "exp" => Function::Exp,
+                "log" => Function::Log,
+                "sin" => Function::Sin,
+                "cos" => Function::Cos,
+                "conj" => Function::Conj,
+                "abs" => Function::Abs,
+                _ => return Err(format!("column {column}: unknown function `{name}`")),
+            };
+            let [argument]: [Syntax; 1] = arguments.try_into().map_err(|arguments: Vec<_>| {
+                format!(
+                    "column {column}: `{name}` takes 1 argument, found {}",
+                    arguments.len()
+                )
+            })?;
+            let argument = check(argument)?;
+            let ty = match function {
+                Function::Abs => Type::Real,
+                _ => Type::Complex,
+            };
+            typed(ty, Node::Call(function, Box::new(argument)))
+        }
+    }
+}
+
+// GLSL for a node, as a `float` if it is real and a `vec2` otherwise. The
+// complex functions are defined in the fragment shader.
+fn glsl(typed: &Typed) -> String {
+    match &typed.node {
+        Node::Real(x) => format!("{x:?}"),
+        Node::I => "vec2(0., 1.)".to_string(),
+        Node::Z => "z".to_string(),
+        Node::C => "c".to_string(),
+        Node::Negate(operand) => format!("(-{})", glsl(operand)),
+        Node::Binary(operator, left, right) => {
+            let real = left.ty == Type::Real && right.ty == Type::Real;
+            match operator {
+                Operator::Add | Operator::Subtract => {
+                    let symbol = if *operator == Operator::Add { '+' } else { '-' };
+                    if real {
+                        format!("({} {symbol} {})", glsl(left), glsl(right))
+                    } else {
+                        format!("({} {symbol} {})", complex(left), complex(right))
+                    }
+                }
+                // A float scales a vec2 just as a real number does.
+                Operator::Multiply if left.ty == Type::Real || right.ty == Type::Real => {
+                    format!("({} * {})", glsl(left), glsl(right))
+                }
+                Operator::Multiply => format!("cmul({}, {})", glsl(left), glsl(right)),
+                Operator::Divide if right.ty == Type::Real => {
+                    format!("({} / {})", glsl(left), glsl(right))
+                }
+                Operator::Divide => format!("cdiv({}, {})", complex(left), glsl(right)),
+                Operator::Power => match right.node {
+                    Node::Real(0.) => "vec2(1., 0.)".to_string(),
+                    Node::Real(n) if n.fract() == 0. && n < 0. => {
+                        format!("cdiv(vec2(1., 0.), Power({}, {:?}))", complex(left), -n)
+                    }
+                    _ if right.ty == Type::Real => {
+                        format!("Power({}, {})", complex(left), glsl(right))
+                    }
+                    _ => format!("cpow({}, {})", complex(left), glsl(right)),
+                },
+            }
+        }
+        Node::Call(function, argument) => match function {
+            Function::Abs if argument.ty == Type::Real => format!("abs({})", glsl(argument)),
+            Function::Abs => format!("length({})", glsl(argument)),
+            function => {
+                let name = match function {
+                    Function::Exp => "cexp",
+                    Function::Log => "clog",
+                    Function::Sin => "csin",
+                    Function::Cos => "ccos",
+                    _ => "conj",
+                };
+                format!("{name}({})", complex(argument))
+            }
+        },
+    }
+}
+
+// GLSL for a node as a `vec2`.
+fn complex(typed: &Typed) -> String {
+    match typed.ty {
+        Type::Real => format!("vec2({}, 0.)", glsl(typed)),
+        Type::Complex => glsl(typed),
+    }
+}
+
+// Real values are kept as complex numbers with no imaginary part, which only
+// changes what `^` and `abs` mean, as their types say.
+fn eval(typed: &Typed, z: [f32; 2], c: [f32; 2]) -> [f32; 2] {
+    match &typed.node {
+        Node::Real(x) => [*x, 0.],
+        Node::I => [0., 1.],
+        Node::Z => z,
+        Node::C => c,
+        Node::Negate(operand) => eval(operand, z, c).map(|x| -x),
+        Node::Binary(operator, left, right) => {
+            let a = eval(left, z, c);
+            let b = eval(right, z, c);
+            match operator {
+                Operator::Add => [a[0] + b[0], a[1] + b[1]],
+                Operator::Subtract => [a[0] - b[0], a[1] - b[1]],
+                Operator::Multiply => mul(a, b),
+                Operator::Divide if right.ty == Type::Real => [a[0] / b[0], a[1] / b[0]],
+                Operator::Divide => div(a, b),
+                Operator::Power => match right.node {
+                    Node::Real(0.) => [1., 0.],
+                    Node::Real(n) if n.fract() == 0. && n < 0. => div([1., 0.], cpu::power(a, -n)),
+                    _ if right.ty == Type::Real => cpu::power(a, b[0]),
+                    _ if a == [0., 0.] => a,
+                    _ => exp(mul(b, log(a))),
+                },
+            }
+        }
+        Node::Call(function, argument) => {
+            let a = eval(argument, z, c);
+            match function {
+                Function::Exp => exp(a),
+                Function::Log => log(a),
+                Function::Sin => [a[0].sin() * a[1].cosh(), a[0].cos() * a[1].sinh()],
+                Function::Cos => [a[0].cos() * a[1].cosh(), -a[0].sin() * a[1].sinh()],
+                Function::Conj => [a[0], -a[1]],
+                Function::Abs => [a[0].hypot(a[1]), 0.],
+            }
+        }
+    }
+}
+
+// Power of `z` a node grows as when `z` is large, or `None` if it grows
+// faster than any power, or by a power not known until it is evaluated.
+// Terms that cancel out, as in `z^3 - z^3`, still count.
+fn degree(typed: &Typed) -> Option<f32> {
+    match &typed.node {
+        Node::Real(_) | Node::I | Node::C => Some(0.),
+        Node::Z => Some(1.),
+        Node::Negate(operand) => degree(operand),
+        Node::Binary(operator, left, right) => {
+            let (a, b) = (degree(left)?, degree(right)?);
+            match operator {
+                Operator::Add | Operator::Subtract => Some(a.max(b)),
+                Operator::Multiply => Some(a + b),
+                Operator::Divide => Some(a - b),
+                Operator::Power => match right.node {
+                    Node::Real(n) => Some(a * n),
+                    _ if a == 0. && b == 0. => Some(0.),
+                    _ => None,
+                },
+            }
+        }
+        Node::Call(function, argument) => {
+            let a = degree(argument)?;
+            match function {
+                Function::Conj | Function::Abs => Some(a),
+                // Slower than any power.
+                Function::Log => Some(0.),
+                _ if a == 0. => Some(0.),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn mul(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] * b[0] - a[1] * b[1], a[0] * b[1] + a[1] * b[0]]
+}
+
+fn div(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    let norm = b[0] * b[0] + b[1] * b[1];
+    [
+        (a[0] * b[0] + a[1] * b[1]) / norm,
+        (a[1] * b[0] - a[0] * b[1]) / norm,
+    ]
+}
+
+fn exp(a: [f32; 2]) -> [f32; 2] {
+    let r = a[0].exp();
+    [r * a[1].cos(), r * a[1].sin()]
+}
+
+fn log(a: [f32; 2]) -> [f32; 2] {
+    [a[0].hypot(a[1]).ln(), a[1].atan2(a[0])]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Syntax {
+        let mut parser = Parser {
+            tokens: tokenize(source).unwrap(),
+            next: 0,
+        };
+        let syntax = parser.expression().unwrap();
+        assert_eq!(parser.next, parser.tokens.len());
+        syntax
+    }
+
+    fn number(x: f64) -> Box<Syntax> {
+        Box::new(Syntax::Number(x))
+    }
+
+    fn name(column: usize, name: &str) -> Box<Syntax> {
+        Box::new(Syntax::Name(column, name.to_string()))
+    }
+
+    fn error(source: &str) -> String {
+        CustomFormula::parse(source).unwrap_err()
+    }
+
+    #[test]
+    fn tokenizes() {
+        assert_eq!(
+            tokenize("z^2 + 1.5e-3*sin(c)").unwrap(),
+            [
+                (1, Token::Name("z".to_string())),
+                (2, Token::Symbol('^')),
+                (3, Token::Number(2.)),
+                (5, Token::Symbol('+')),
+                (7, Token::Number(1.5e-3)),
+                (13, Token::Symbol('*')),
+                (14, Token::Name("sin".to_string())),
+                (17, Token::Symbol('(')),
+                (18, Token::Name("c".to_string())),
+                (19, Token::Symbol(')')),
+            ]
+        );
+        // Columns count characters rather than bytes.
+        assert_eq!(
+            tokenize("é + z").unwrap()[2],
+            (5, Token::Name("z".to_string()))
+        );
+        assert_eq!(tokenize("z # 2").unwrap_err(), "column 3: unexpected `#`");
+        assert_eq!(
+            tokenize("1.2.3").unwrap_err(),
+            "column 1: invalid number `1.2.3`"
+        );
+    }
+
+    #[test]
+    fn minus_binds_looser_than_power() {
+        assert_eq!(
+            parse("-z^2"),
+            Syntax::Negate(Box::new(Syntax::Binary(
+                Operator::Power,
+                name(2, "z"),
+                number(2.)
+            )))
+        );
+        assert_eq!(
+            parse("z^-2"),
+            Syntax::Binary(
+                Operator::Power,
+                name(1, "z"),
+                Box::new(Syntax::Negate(number(2.)))
+            )
+        );
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(
+            parse("2^3^2"),
+            Syntax::Binary(
+                Operator::Power,
+                number(2.),
+                Box::new(Syntax::Binary(Operator::Power, number(3.), number(2.)))
+            )
+        );
+    }
+
+    #[test]
+    fn other_operators_are_left_associative() {
+        assert_eq!(
+            parse("1 - 2 - 3"),
+            Syntax::Binary(
+                Operator::Subtract,
+                Box::new(Syntax::Binary(Operator::Subtract, number(1.), number(2.))),
+                number(3.)
+            )
+        );
+        assert_eq!(
+            parse("1 + 2 * 3"),
+            Syntax::Binary(
+                Operator::Add,
+                number(1.),
+                Box::new(Syntax::Binary(Operator::Multiply, number(2.), number(3.)))
+            )
+        );
+    }
+
+    #[test]
+    fn evaluates() {
+        let eval = |source: &str, z| CustomFormula::parse(source).unwrap().eval(z, [0., 0.]);
+        assert_eq!(eval("-z^2", [0., 1.]), [1., 0.]);
+        assert_eq!(eval("2^3^2", [0., 0.]), [512., 0.]);
+        assert_eq!(eval("1 - 2 - 3", [0., 0.]), [-4., 0.]);
+        assert_eq!(eval("i*i", [0., 0.]), [-1., 0.]);
+        assert_eq!(eval("abs(z)", [3., 4.]), [5., 0.]);
+        assert_eq!(eval("conj(z)", [3., 4.]), [3., -4.]);
+    }
+
+    #[test]
+    fn finds_the_degree() {
+        let degree = |source: &str| CustomFormula::parse(source).unwrap().degree();
+        assert_eq!(degree("z^2 + c"), Some(2.));
+        assert_eq!(degree("sin(c) * z^3 + z - c"), Some(3.));
+        assert_eq!(degree("z * conj(z) * z / 2"), Some(3.));
+        assert_eq!(degree("(z^2 + c)^2.5"), Some(5.));
+        assert_eq!(degree("z^4 / (z + 1)"), Some(3.));
+        assert_eq!(degree("z^2 + log(z)"), Some(2.));
+        assert_eq!(degree("abs(z)^1.5 + exp(c)"), Some(1.5));
+        assert_eq!(degree("z^-2"), None);
+        assert_eq!(degree("z + c"), None);
+        assert_eq!(degree("exp(z)"), None);
+        assert_eq!(degree("z^c"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_formulas() {
+        assert_eq!(error("z + w"), "column 5: unknown variable `w`");
+        assert_eq!(error("tan(z)"), "column 1: unknown function `tan`");
+        assert_eq!(
+            error("sin(z, c)"),
+            "column 1: `sin` takes 1 argument, found 2"
+        );
+        assert_eq!(
+            error("1e39 * z"),
+            format!("{} is too large for a float", 1e39)
+        );
+        assert_eq!(error("(z + c"), "expected `)` at the end");
+        assert_eq!(error("z + c)"), "column 6: unexpected `)`");
+        assert_eq!(error("z +"), "unexpected end of the formula");
+        assert_eq!(error("z * * c"), "column 5: unexpected `*`");
+    }
+
+    #[test]
+    fn limits_length_in_characters() {
+        // Ideographic spaces take 3 bytes each.
+        let spaces = "\u{3000}".repeat(MAX_LENGTH - 1);
+        assert!(CustomFormula::parse(&format!("z{spaces}")).is_ok());
+        assert_eq!(
+            error(&format!("zc{spaces}")),
+            format!("formulas are limited to {MAX_LENGTH} characters")
+        );
+    }
+
+    #[test]
+    fn compiles_to_glsl() {
+        let glsl = |source: &str| CustomFormula::parse(source).unwrap().glsl();
+        assert_eq!(glsl("z^2 + c"), "(Power(z, 2.0) + c)");
+        assert_eq!(glsl("2*z"), "(2.0 * z)");
+        assert_eq!(glsl("z*c"), "cmul(z, c)");
+        assert_eq!(glsl("1/z"), "cdiv(vec2(1.0, 0.), z)");
+        assert_eq!(glsl("z^-2"), "cdiv(vec2(1., 0.), Power(z, 2.0))");
+        assert_eq!(glsl("abs(z)"), "vec2(length(z), 0.)");
+    }
+
+    // A value of the generated GLSL.
+    #[derive(Clone, Copy, Debug)]
+    enum Value {
+        Float(f32),
+        Vec2([f32; 2]),
+    }
+
+    impl Value {
+        fn float(self) -> f32 {
+            match self {
+                Value::Float(x) => x,
+                Value::Vec2(v) => panic!("expected a float, found {v:?}"),
+            }
+        }
+
+        fn vec2(self) -> [f32; 2] {
+            match self {
+                Value::Vec2(v) => v,
+                Value::Float(x) => panic!("expected a vec2, found {x}"),
+            }
+        }
+    }
+
+    // Evaluates the GLSL `glsl()` writes, with the complex functions of the
+    // fragment shader.
+    struct Glsl {
+        tokens: Vec<(usize, Token)>,
+        next: usize,
+        z: [f32; 2],
+        c: [f32; 2],
+    }
+
+    impl Glsl {
+        fn eat(&mut self, symbol: char) -> bool {
+            let found =
+                self.tokens.get(self.next).map(|(_, token)| token) == Some(&Token::Symbol(symbol));
+            self.next += found as usize;
+            found
+        }
+
+        fn expression(&mut self) -> Value {
+            let mut left = self.term();
+            loop {
+                let sign = if self.eat('+') {
+                    1.
+                } else if self.eat('-') {
+                    -1.
+                } else {
+                    return left;
+                };
+                let right = self.term();
+                left = match (left, right) {
+                    (Value::Float(a), Value::Float(b)) => Value::Float(a + sign * b),
+                    (a, b) => {
+                        let (a, b) = (a.vec2(), b.vec2());
+                        Value::Vec2([a[0] + sign * b[0], a[1] + sign * b[1]])
+                    }
+                };
+            }
+        }
+
+        fn term(&mut self) -> Value {
+            let mut left = self.unary();
+            loop {
+                if self.eat('*') {
+                    left = match (left, self.unary()) {
+                        (Value::Float(a), Value::Float(b)) => Value::Float(a * b),
+                        (Value::Float(s), Value::Vec2(v)) | (Value::Vec2(v), Value::Float(s)) => {
+                            Value::Vec2([v[0] * s, v[1] * s])
+                        }
+                        (a, b) => panic!("component-wise {a:?} * {b:?}"),
+                    };
+                } else if self.eat('/') {
+                    let b = self.unary().float();
+                    left = match left {
+                        Value::Float(a) => Value::Float(a / b),
+                        Value::Vec2(a) => Value::Vec2([a[0] / b, a[1] / b]),
+                    };
+                } else {
+                    return left;
+                }
+            }
+        }
+
+        fn unary(&mut self) -> Value {
+            if self.eat('-') {
+                return match self.unary() {
+                    Value::Float(x) => Value::Float(-x),
+                    Value::Vec2(v) => Value::Vec2([-v[0], -v[1]]),
+                };
+            }
+            self.atom()
+        }
+
+        fn atom(&mut self) -> Value {
+            let (_, token) = self.tokens[self.next].clone();
+            self.next += 1;
+            match token {
+                Token::Number(x) => Value::Float(x as f32),
+                Token::Symbol('(') => {
+                    let inner = self.expression();
+                    assert!(self.eat(')'));
+                    inner
+                }
+                Token::Name(name) if name == "z" => Value::Vec2(self.z),
+                Token::Name(name) if name == "c" => Value::Vec2(self.c),
+                Token::Name(name) => {
+                    assert!(self.eat('('));
+                    let mut arguments = vec![self.expression()];
+                    while self.eat(',') {
+                        arguments.push(self.expression());
+                    }
+                    assert!(self.eat(')'));
+                    call(&name, &arguments)
+                }
+                token => panic!("unexpected {token}"),
+            }
+        }
+    }
+
+    fn call(name: &str, arguments: &[Value]) -> Value {
+        let a = arguments[0];
+        let b = arguments.get(1).copied();
+        Value::Vec2(match name {
+            "vec2" => [a.float(), b.unwrap().float()],
+            "cmul" => mul(a.vec2(), b.unwrap().vec2()),
+            "cdiv" => div(a.vec2(), b.unwrap().vec2()),
+            "cexp" => exp(a.vec2()),
+            "clog" => log(a.vec2()),
+            "csin" => {
+                let [x, y] = a.vec2();
+                [x.sin() * y.cosh(), x.cos() * y.sinh()]
+            }
+            "ccos" => {
+                let [x, y] = a.vec2();
+                [x.cos() * y.cosh(), -x.sin() * y.sinh()]
+            }
+            "cpow" => match a.vec2() {
+                [0., 0.] => [0., 0.],
+                a => exp(mul(b.unwrap().vec2(), log(a))),
+            },
+            "conj" => {
+                let [x, y] = a.vec2();
+                [x, -y]
+            }
+            "Power" => cpu::power(a.vec2(), b.unwrap().float()),
+            "length" => return Value::Float(a.vec2()[0].hypot(a.vec2()[1])),
+            "abs" => return Value::Float(a.float().abs()),
+            _ => panic!("unknown function `{name}`"),
+        })
+    }
+
+    #[test]
+    fn glsl_agrees_with_eval() {
+        let points = [[0.3, -0.2], [-1.1, 0.7], [0., 0.], [2., 1.5]];
+        for source in [
+            "z^2 + c",
+            "-z^2 + c",
+            "z^3 + sin(c)*z + c",
+            "z^-2 + c",
+            "z^2.5 + c",
+            "z^(1 + i) + c",
+            "2^z + c",
+            "exp(z) / (z + 2) + c",
+            "log(z + 3) * cos(c) - z/2",
+            "conj(z)^2 + c",
+            "abs(z) * z - abs(-2) + pi*c",
+            "(z*z - c) * (i - 1)",
+        ] {
+            let formula = CustomFormula::parse(source).unwrap();
+            for z in points {
+                for c in points {
+                    let mut glsl = Glsl {
+                        tokens: tokenize(&formula.glsl()).unwrap(),
+                        next: 0,
+                        z,
+                        c,
+                    };
+                    let expected = glsl.expression().vec2();
+                    assert_eq!(glsl.next, glsl.tokens.len());
+                    let found = formula.eval(z, c);
+                    for (found, expected) in found.iter().zip(expected) {
+                        assert!(
+                            found == &expected
+                                || (found.is_nan() && expected.is_nan())
+                                || (found - expected).abs() <= 1e-4 * expected.abs().max(1.),
+                            "{source} at z = {z:?}, c = {c:?}: {found:?} != {expected:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
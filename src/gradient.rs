@@ -0,0 +1,530 @@
+use std::f32::consts::PI;
+
+use crate::palette::{ColorStop, Palette};
+
+// Stops each GIMP segment is sampled into, so that its blending survives the
+// linear interpolation between stops.
+const SEGMENT_SAMPLES: usize = 16;
+
+// Number of positions along an Ultra Fractal gradient.
+const UGR_INDICES: i64 = 400;
+
+// Reads the palettes in a gradient file, telling the format from the extension
+// of `file_name`.
+pub fn parse(file_name: &str, text: &str) -> Result<Vec<Palette>, String> {
+    let (name, extension) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
+    match extension.to_ascii_lowercase().as_str() {
+        "ugr" => parse_ugr(text),
+        "ggr" => Ok(vec![parse_ggr(text)?]),
+        "map" => Ok(vec![parse_map(name, text)?]),
+        _ => Err(format!("unsupported gradient file `{file_name}`")),
+    }
+}
+
+// Fractint palette: one `red green blue` line per color, each in 0..=255, and
+// anything after them taken as a comment.
+pub fn parse_map(name: &str, text: &str) -> Result<Palette, String> {
+    let mut colors = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut components = line.split_whitespace();
+        let mut color = [0; 3];
+        for component in &mut color {
+            let value = components
+                .next()
+                .ok_or_else(|| format!("line {}: expected three color components", number + 1))?;
+            *component = value.parse().map_err(|_| {
+                format!(
+                    "line {}: color components must be in 0..=255, found `{value}`",
+                    number + 1
+                )
+            })?;
+        }
+        colors.push(color);
+    }
+
+    Palette::uniform(name, &colors)
+}
+
+// GIMP gradient: a header and name, the number of segments, and then a line
+// per segment:
+//
+//     left middle right  r g b a  r g b a  blending coloring [left right]
+//
+// with positions and colors in [0, 1].
+pub fn parse_ggr(text: &str) -> Result<Palette, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    match lines.next() {
+        Some((_, "GIMP Gradient")) => {}
+        _ => return Err("line 1: expected `GIMP Gradient`".to_string()),
+    }
+    let (number, line) = lines
+        .next()
+        .ok_or_else(|| "missing gradient name".to_string())?;
+    let name = line
+        .strip_prefix("Name:")
+        .ok_or_else(|| format!("line {number}: expected `Name: ...`"))?
+        .trim();
+    let (number, line) = lines
+        .next()
+        .ok_or_else(|| "missing number of segments".to_string())?;
+    let count = line
+        .parse::<usize>()
+        .map_err(|_| format!("line {number}: expected the number of segments, found `{line}`"))?;
+
+    let mut stops = Vec::new();
+    for _ in 0..count {
+        let (number, line) = lines
+            .next()
+            .ok_or_else(|| format!("expected {count} segments"))?;
+        let segment = GgrSegment::parse(line).map_err(|err| format!("line {number}: {err}"))?;
+        stops.extend(segment.stops());
+    }
+    if let Some((number, _)) = lines.next() {
+        return Err(format!("line {number}: expected only {count} segments"));
+    }
+    if stops.is_empty() {
+        return Err("the gradient has no segments".to_string());
+    }
+
+    Palette::new(name, stops)
+}
+
+struct GgrSegment {
+    left: f32,
+    middle: f32,
+    right: f32,
+    left_color: [f32; 3],
+    right_color: [f32; 3],
+    blending: u32,
+    coloring: u32,
+}
+
+impl GgrSegment {
+    fn parse(line: &str) -> Result<GgrSegment, String> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.len() < 13 {
+            return Err(format!(
+                "expected at least 13 fields, found {}",
+                fields.len()
+            ));
+        }
+        let number = |i: usize| {
+            fields[i]
+                .parse::<f32>()
+                .ok()
+                .filter(|value| (0. ..=1.).contains(value))
+                .ok_or_else(|| format!("expected a number in [0, 1], found `{}`", fields[i]))
+        };
+        let kind = |i: usize, max: u32| {
+            fields[i]
+                .parse::<u32>()
+                .ok()
+                .filter(|&value| value <= max)
+                .ok_or_else(|| format!("expected a type in 0..={max}, found `{}`", fields[i]))
+        };
+
+        let segment = GgrSegment {
+            left: number(0)?,
+            middle: number(1)?,
+            right: number(2)?,
+            left_color: [number(3)?, number(4)?, number(5)?],
+            right_color: [number(7)?, number(8)?, number(9)?],
+            blending: kind(11, 5)?,
+            coloring: kind(12, 2)?,
+        };
+        if !(segment.left <= segment.middle && segment.middle <= segment.right) {
+            return Err("segment positions must be in order".to_string());
+        }
+        Ok(segment)
+    }
+
+    // Samples the segment, leaving out its right end where the next segment
+    // starts. The end of the last segment is where the palette wraps around.
+    fn stops(&self) -> Vec<ColorStop> {
+        let width = self.right - self.left;
+        if width <= 0. {
+            return Vec::new();
+        }
+
+        let middle = (self.middle - self.left) / width;
+        (0..SEGMENT_SAMPLES)
+            .map(|i| {
+                let t = i as f32 / SEGMENT_SAMPLES as f32;
+                ColorStop {
+                    position: (self.left + t * width).min(1. - f32::EPSILON),
+                    color: self.color(self.blend(t, middle)),
+                }
+            })
+            .collect()
+    }
+
+    // https://gitlab.gnome.org/GNOME/gimp/-/blob/master/app/core/gimpgradient.c
+    fn blend(&self, t: f32, middle: f32) -> f32 {
+        let linear = if t <= middle {
+            if middle > 0. {
+                0.5 * t / middle
+            } else {
+                0.5
+            }
+        } else if middle < 1. {
+            0.5 + 0.5 * (t - middle) / (1. - middle)
+        } else {
+            1.
+        };
+        match self.blending {
+            1 => t.powf(0.5f32.ln() / middle.max(1e-6).ln()),
+            2 => ((-PI / 2. + PI * linear).sin() + 1.) / 2.,
+            3 => (1. - (linear - 1.).powi(2)).sqrt(),
+            4 => 1. - (1. - linear.powi(2)).sqrt(),
+            5 => (t >= middle) as u8 as f32,
+            _ => linear,
+        }
+    }
+
+    fn color(&self, factor: f32) -> [u8; 3] {
+        let color = match self.coloring {
+            0 => [0, 1, 2]
+                .map(|i| self.left_color[i] + (self.right_color[i] - self.left_color[i]) * factor),
+            coloring => {
+                let [h0, s0, v0] = rgb_to_hsv(self.left_color);
+                let [h1, s1, v1] = rgb_to_hsv(self.right_color);
+                // 1 turns counterclockwise and 2 clockwise around the hue circle.
+                let h1 = match coloring {
+                    1 if h1 < h0 => h1 + 1.,
+                    2 if h1 > h0 => h1 - 1.,
+                    _ => h1,
+                };
+                hsv_to_rgb([
+                    (h0 + (h1 - h0) * factor).rem_euclid(1.),
+                    s0 + (s1 - s0) * factor,
+                    v0 + (v1 - v0) * factor,
+                ])
+            }
+        };
+        color.map(|c| (c.clamp(0., 1.) * 255.).round() as u8)
+    }
+}
+
+fn rgb_to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let h = if delta == 0. {
+        0.
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.) / 6.
+    } else if max == g {
+        ((b - r) / delta + 2.) / 6.
+    } else {
+        ((r - g) / delta + 4.) / 6.
+    };
+    let s = if max == 0. { 0. } else { delta / max };
+    [h, s, max]
+}
+
+fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let f = |n: f32| {
+        let k = (n + h * 6.) % 6.;
+        v - v * s * k.min(4. - k).clamp(0., 1.)
+    };
+    [f(5.), f(3.), f(1.)]
+}
+
+// Ultra Fractal gradients, any number of them per file:
+//
+//     name {
+//     gradient:
+//       title="Name" smooth=no
+//       index=0 color=5153808
+//       index=200 color=16777215
+//     opacity:
+//       ...
+//     }
+//
+// Indices run from 0 to 399 and colors are `blue << 16 | green << 8 | red`.
+pub fn parse_ugr(text: &str) -> Result<Vec<Palette>, String> {
+    let mut palettes = Vec::new();
+    // The name and stops of the gradient being read, and whether its
+    // `gradient:` section is.
+    let mut current: Option<(String, Vec<ColorStop>, bool)> = None;
+    let mut index = None;
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let Some((name, stops, in_gradient)) = &mut current else {
+            let name = line
+                .strip_suffix('{')
+                .ok_or_else(|| format!("line {number}: expected `name {{`"))?;
+            current = Some((name.trim().to_string(), Vec::new(), false));
+            continue;
+        };
+        if line == "}" {
+            if let Some(index) = index.take() {
+                return Err(format!("line {number}: index {index} has no color"));
+            }
+            let (name, stops, _) = current.take().unwrap();
+            if stops.is_empty() {
+                return Err(format!("line {number}: gradient `{name}` has no colors"));
+            }
+            palettes.push(Palette::new(&name, stops)?);
+            continue;
+        }
+        if let Some(section) = line.strip_suffix(':') {
+            *in_gradient = section.trim() == "gradient";
+            continue;
+        }
+        if !*in_gradient {
+            continue;
+        }
+
+        for (key, value) in ugr_fields(line).map_err(|err| format!("line {number}: {err}"))? {
+            let invalid = || format!("line {number}: invalid `{key}`: `{value}`");
+            match key {
+                "title" => *name = value.to_string(),
+                "index" => index = Some(value.parse::<i64>().map_err(|_| invalid())?),
+                "color" => {
+                    let color = value.parse::<u32>().map_err(|_| invalid())?;
+                    let index = index
+                        .take()
+                        .ok_or_else(|| format!("line {number}: color without an index"))?;
+                    stops.push(ColorStop {
+                        position: index.rem_euclid(UGR_INDICES) as f32 / UGR_INDICES as f32,
+                        color: [color & 0xff, color >> 8 & 0xff, color >> 16 & 0xff]
+                            .map(|c| c as u8),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some((name, _, _)) = current {
+        return Err(format!("gradient `{name}` is missing its closing `}}`"));
+    }
+    if palettes.is_empty() {
+        return Err("the file has no gradients".to_string());
+    }
+
+    Ok(palettes)
+}
+
+// Splits a line into `key=value` pairs, where values may be quoted.
+fn ugr_fields(line: &str) -> Result<Vec<(&str, &str)>, String> {
+    let mut fields = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let (key, after) = rest
+            .split_once('=')
+            .ok_or_else(|| format!("expected `key=value`, found `{rest}`"))?;
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => quoted
+                .split_once('"')
+                .ok_or_else(|| format!("unterminated quote in `{key}`"))?,
+            None => after.split_once(char::is_whitespace).unwrap_or((after, "")),
+        };
+        fields.push((key.trim(), value));
+        rest = after.trim_start();
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = include_str!("../tests/fixtures/sunset.map");
+    const GGR: &str = include_str!("../tests/fixtures/sunset.ggr");
+    const UGR: &str = include_str!("../tests/fixtures/sunsets.ugr");
+
+    fn stop(position: f32, color: [u8; 3]) -> ColorStop {
+        ColorStop { position, color }
+    }
+
+    #[test]
+    fn parses_map() {
+        let palette = parse_map("sunset", MAP).unwrap();
+        assert_eq!(palette.name, "sunset");
+        assert_eq!(
+            palette.stops,
+            [
+                stop(0., [255, 0, 0]),
+                stop(0.25, [255, 255, 0]),
+                stop(0.5, [0, 0, 255]),
+                stop(0.75, [0, 0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_ggr() {
+        let palette = parse_ggr(GGR).unwrap();
+        assert_eq!(palette.name, "Sunset");
+        assert_eq!(palette.stops.len(), 2 * SEGMENT_SAMPLES);
+        // The first segment blends red into yellow in RGB, and is halfway at
+        // its middle.
+        assert_eq!(palette.stops[0], stop(0., [255, 0, 0]));
+        assert_eq!(palette.stops[8], stop(0.25, [255, 128, 0]));
+        // The second blends yellow into blue in HSV, through green.
+        assert_eq!(palette.stops[16], stop(0.5, [255, 255, 0]));
+        assert_eq!(palette.stops[24], stop(0.75, [0, 255, 128]));
+    }
+
+    #[test]
+    fn parses_ugr() {
+        let palettes = parse_ugr(UGR).unwrap();
+        assert_eq!(palettes.len(), 2);
+        assert_eq!(palettes[0].name, "Sunset");
+        assert_eq!(
+            palettes[0].stops,
+            [stop(0., [255, 0, 0]), stop(0.5, [255, 255, 0])]
+        );
+        // Indices wrap around, and the stops are sorted.
+        assert_eq!(palettes[1].name, "Night Sky");
+        assert_eq!(
+            palettes[1].stops,
+            [stop(0.25, [0, 0, 0]), stop(0.75, [0, 0, 255])]
+        );
+    }
+
+    #[test]
+    fn tells_formats_from_extensions() {
+        assert_eq!(parse("sunset.MAP", MAP).unwrap()[0].name, "sunset");
+        assert_eq!(parse("sunset.ggr", GGR).unwrap()[0].name, "Sunset");
+        assert_eq!(parse("sunsets.ugr", UGR).unwrap().len(), 2);
+        assert_eq!(
+            parse("sunset.png", MAP).unwrap_err(),
+            "unsupported gradient file `sunset.png`"
+        );
+        assert!(parse("sunset", MAP).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_map() {
+        for (text, error) in [
+            ("", "a palette needs at least one color"),
+            ("255 0 0\n255 0", "line 2: expected three color components"),
+            (
+                "255 0 256",
+                "line 1: color components must be in 0..=255, found `256`",
+            ),
+            (
+                "255 red 0",
+                "line 1: color components must be in 0..=255, found `red`",
+            ),
+        ] {
+            assert_eq!(parse_map("bad", text).unwrap_err(), error, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_ggr() {
+        let segment = "0 0.5 1 1 0 0 1 0 0 1 1 0 0";
+        for (text, error) in [
+            (
+                String::new(),
+                "line 1: expected `GIMP Gradient`".to_string(),
+            ),
+            (
+                "GIMP Palette\nName: Bad\n1".to_string(),
+                "line 1: expected `GIMP Gradient`".to_string(),
+            ),
+            (
+                "GIMP Gradient".to_string(),
+                "missing gradient name".to_string(),
+            ),
+            (
+                "GIMP Gradient\nBad\n1".to_string(),
+                "line 2: expected `Name: ...`".to_string(),
+            ),
+            (
+                "GIMP Gradient\nName: Bad\nmany".to_string(),
+                "line 3: expected the number of segments, found `many`".to_string(),
+            ),
+            (
+                "GIMP Gradient\nName: Bad\n2\n".to_string() + segment,
+                "expected 2 segments".to_string(),
+            ),
+            (
+                format!("GIMP Gradient\nName: Bad\n1\n{segment}\n{segment}"),
+                "line 5: expected only 1 segments".to_string(),
+            ),
+            (
+                "GIMP Gradient\nName: Bad\n0".to_string(),
+                "the gradient has no segments".to_string(),
+            ),
+            (
+                "GIMP Gradient\nName: Bad\n1\n0 0.5 1".to_string(),
+                "line 4: expected at least 13 fields, found 3".to_string(),
+            ),
+            (
+                "GIMP Gradient\nName: Bad\n1\n0 0.5 1 2 0 0 1 0 0 1 1 0 0".to_string(),
+                "line 4: expected a number in [0, 1], found `2`".to_string(),
+            ),
+            (
+                "GIMP Gradient\nName: Bad\n1\n0 0.5 1 1 0 0 1 0 0 1 1 6 0".to_string(),
+                "line 4: expected a type in 0..=5, found `6`".to_string(),
+            ),
+            (
+                "GIMP Gradient\nName: Bad\n1\n0.5 0.2 1 1 0 0 1 0 0 1 1 0 0".to_string(),
+                "line 4: segment positions must be in order".to_string(),
+            ),
+        ] {
+            assert_eq!(parse_ggr(&text).unwrap_err(), error, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_ugr() {
+        for (text, error) in [
+            ("", "the file has no gradients"),
+            ("; only a comment", "the file has no gradients"),
+            ("bad", "line 1: expected `name {`"),
+            (
+                "bad {\ngradient:\nindex=0 color=255",
+                "gradient `bad` is missing its closing `}`",
+            ),
+            (
+                "bad {\ngradient:\n}",
+                "line 3: gradient `bad` has no colors",
+            ),
+            (
+                "bad {\ngradient:\nindex=0\n}",
+                "line 4: index 0 has no color",
+            ),
+            (
+                "bad {\ngradient:\ncolor=255\n}",
+                "line 3: color without an index",
+            ),
+            (
+                "bad {\ngradient:\nindex=zero color=255\n}",
+                "line 3: invalid `index`: `zero`",
+            ),
+            (
+                "bad {\ngradient:\nindex=0 color=-1\n}",
+                "line 3: invalid `color`: `-1`",
+            ),
+            (
+                "bad {\ngradient:\ntitle=\"Bad index=0 color=255\n}",
+                "line 3: unterminated quote in `title`",
+            ),
+            (
+                "bad {\ngradient:\nindex=0 255\n}",
+                "line 3: expected `key=value`, found `255`",
+            ),
+        ] {
+            assert_eq!(parse_ugr(text).unwrap_err(), error, "{text:?}");
+        }
+    }
+}
@@ -1,10 +1,36 @@
+pub mod cpu;
+pub mod error;
+pub mod export;
+pub mod expression;
+pub mod gradient;
+pub mod palette;
+pub mod perturbation;
+pub mod shader;
+pub mod share;
+pub mod trap;
 mod utils;
+pub mod viewport;
 
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, Ref, RefCell},
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
 
-use wasm_bindgen::prelude::*;
+use dashu_float::FBig;
+use error::Error;
+use expression::CustomFormula;
+use palette::Palette;
+use shader::{GlVersion, ShaderConfig};
+use share::SharedView;
+use trap::OrbitTrap;
+use viewport::Viewport;
+use wasm_bindgen::{prelude::*, Clamped};
 use web_sys::{
-    HtmlCanvasElement, WebGl2RenderingContext, WebGlProgram, WebGlShader, WheelEvent, Window,
+    CanvasRenderingContext2d, HtmlCanvasElement, HtmlElement, ImageData, KeyboardEvent,
+    PointerEvent, WebGl2RenderingContext, WebGlBuffer, WebGlContextAttributes, WebGlFramebuffer,
+    WebGlProgram, WebGlRenderingContext, WebGlShader, WebGlTexture, WebGlUniformLocation,
+    WheelEvent, Window,
 };
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
@@ -19,243 +45,1981 @@ extern "C" {
     fn log(s: &str);
 }
 
-const ZOOM_IN: f32 = 0.8;
+const ZOOM_IN: f64 = 0.8;
+// Fraction of the screen moved by one key press.
+const PAN_STEP: f64 = 0.1;
+
+const INITIAL_ITERATIONS: i32 = 100;
+// Beyond this the reference orbit takes too much memory, and no frame ends.
+const MAX_ITERATIONS: i32 = 1 << 20;
+const INITIAL_CENTER: [f64; 2] = [-0.7, 0.];
+const INITIAL_HALF_WIDTH: f64 = 1.8;
+
+const MAX_SAMPLES: u32 = 8;
+
+// Linked programs kept for switching back to, one per shader configuration.
+const MAX_PROGRAMS: usize = 16;
+
+// Powers of `Formula::Multibrot`. Escaping past 2 only holds from 2 on.
+const MIN_POWER: f32 = 2.;
+const MAX_POWER: f32 = 16.;
+
+// Fractions of the resolution the view is previewed at after it changes.
+const PREVIEW_DIVISORS: [u32; 2] = [4, 2];
+// Iterations drawn per frame at full resolution, at most, so that no frame
+// takes too long. The rows of a frame are as many as fit in this if every
+// sample reaches the iteration limit, and at least one.
+const REFINE_ITERATIONS: u64 = 1 << 28;
+// The same for the CPU renderer, which is many times slower.
+const CPU_REFINE_ITERATIONS: u64 = 1 << 21;
+
+// Passes through the palette per second when cycling it.
+const CYCLING_SPEED: f32 = 0.1;
+
+// Milliseconds between writes of the view into the URL hash.
+const HASH_DELAY: i32 = 250;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fractal {
+    Mandelbrot = 0,
+    Julia = 1,
+}
+
+// What is iterated: z^2 + c, z^power + c for any real power of at least 2,
+// z^2 + c with absolute values or the conjugate taken of parts of z or z^2,
+// or a formula typed by the user. Only `Quadratic` has the double precision
+// and perturbation shaders, so the others always render in single precision.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Formula {
+    Quadratic = 0,
+    Multibrot = 1,
+    BurningShip = 2,
+    Tricorn = 3,
+    Celtic = 4,
+    Buffalo = 5,
+    Custom = 6,
+}
+
+// `Double` emulates double precision in the shader so that the view can be
+// zoomed in much further, at the cost of speed. `Perturbation` iterates each
+// pixel relative to an orbit computed on the CPU with arbitrary precision,
+// which keeps working far beyond that.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Precision {
+    Single = 0,
+    Double = 1,
+    Perturbation = 2,
+}
+
+// `Distance` darkens the outside of the set by its estimated distance from the
+// boundary, which brings out filaments too thin for the escape time to show.
+// `Trap` colors it by how close orbits came to the orbit trap.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Coloring {
+    Smooth = 0,
+    Distance = 1,
+    Trap = 2,
+}
+
+// `Stalks` are a cross too, drawn over the smooth coloring rather than
+// replacing it.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrapShape {
+    Point = 0,
+    Line = 1,
+    Circle = 2,
+    Cross = 3,
+    Stalks = 4,
+}
+
+// How points inside the set are colored: `Magnitude` by where their orbit
+// ends, `Period` by the length of the cycle it is drawn into, and `Distance`
+// like `Period`, darkened by their estimated distance from the boundary.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interior {
+    Black = 0,
+    Magnitude = 1,
+    Period = 2,
+    Distance = 3,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward = 0,
+    Backward = 1,
+}
+
+// Everything that decides what is drawn, shared by the event handlers.
+#[derive(Clone)]
+struct State {
+    viewport: Viewport,
+    iterations: i32,
+    fractal: Fractal,
+    c: [f32; 2],
+    formula: Formula,
+    // Only used by `Formula::Multibrot`.
+    power: f32,
+    // Only used by `Formula::Custom`.
+    custom: CustomFormula,
+    precision: Precision,
+    palette: Palette,
+    // Passes through the palette per `PALETTE_PERIOD` iterations.
+    palette_speed: f32,
+    // Shifts the palette by a fraction of it.
+    palette_offset: f32,
+    // Samples per pixel along each axis once the view is refined.
+    samples: u32,
+    jitter: bool,
+    coloring: Coloring,
+    interior: Interior,
+    trap: OrbitTrap,
+}
+
+impl State {
+    // The precision the view is actually rendered with.
+    fn precision(&self) -> Precision {
+        match self.formula {
+            Formula::Quadratic => self.precision,
+            _ => Precision::Single,
+        }
+    }
+
+    fn shader_config(&self, version: GlVersion) -> ShaderConfig {
+        ShaderConfig {
+            version,
+            formula: self.formula,
+            custom: match self.formula {
+                Formula::Custom => self.custom.glsl(),
+                _ => String::new(),
+            },
+            // The reference orbit needs float textures, which WebGL 1 may lack.
+            precision: match (self.precision(), version) {
+                (Precision::Perturbation, GlVersion::WebGl1) => Precision::Double,
+                (precision, _) => precision,
+            },
+            coloring: self.coloring,
+        }
+    }
 
-static VERTEX_SHADER: &'static str = r#"#version 300 es
-    in vec2 a_position;
+    fn cpu_params(&self) -> cpu::Params {
+        cpu::Params {
+            viewport: self.viewport.clone(),
+            iterations: self.iterations,
+            fractal: self.fractal,
+            c: self.c,
+            formula: self.formula,
+            power: self.power,
+            custom: self.custom.clone(),
+            palette: self.palette.clone(),
+            palette_speed: self.palette_speed,
+            palette_offset: self.palette_offset,
+            samples: self.samples,
+            jitter: self.jitter,
+            coloring: self.coloring,
+            interior: self.interior,
+            trap: self.trap,
+        }
+    }
+
+    fn degree(&self) -> f32 {
+        match self.formula {
+            Formula::Multibrot => self.power,
+            Formula::Custom => self.custom.degree().unwrap_or(2.),
+            _ => 2.,
+        }
+    }
 
-    void main() {
-        gl_Position = vec4(a_position, 0.0, 1.0);
+    fn shared(&self) -> SharedView {
+        SharedView {
+            fractal: self.fractal,
+            center: self.viewport.center.clone(),
+            half_width: self.viewport.half_width,
+            rotation: self.viewport.rotation,
+            iterations: self.iterations,
+            c: self.c,
+            formula: self.formula,
+            power: self.power,
+            custom: self.custom.clone(),
+            precision: self.precision,
+            // An imported palette may share its name with a built-in one.
+            palette: Palette::builtin(&self.palette.name)
+                .filter(|builtin| *builtin == self.palette)
+                .map(|_| self.palette.name.clone()),
+            palette_speed: self.palette_speed,
+            palette_offset: self.palette_offset,
+        }
     }
-"#;
 
-static FRAGMENT_SHADER: &'static str = r#"#version 300 es
-    precision highp float;
-    precision highp int;
+    // Shows the view of a link, keeping the aspect ratio of the canvas.
+    fn restore(&mut self, view: SharedView) {
+        self.viewport.center = view.center;
+        self.viewport.half_width = view.half_width;
+        self.viewport.rotation = view.rotation;
+        self.iterations = view.iterations;
+        self.fractal = view.fractal;
+        self.c = view.c;
+        self.formula = view.formula;
+        self.power = view.power;
+        self.custom = view.custom;
+        self.precision = view.precision;
+        self.palette_speed = view.palette_speed;
+        self.palette_offset = view.palette_offset;
+        if let Some(name) = view.palette {
+            match Palette::builtin(&name) {
+                Some(palette) => self.palette = palette,
+                None => log(&format!("unknown palette `{name}`")),
+            }
+        }
+    }
+}
 
-    uniform vec2	min;
-    uniform vec2	max;
+#[wasm_bindgen]
+pub struct Explorer {
+    renderer: Rc<Renderer>,
+    state: Rc<RefCell<State>>,
+    render_loop: Rc<RenderLoop>,
+}
 
-    uniform vec2	resolution;
-    uniform int		iterations;
+// Palette cycling, which shifts the palette offset every animation frame.
+struct Cycling {
+    // Passes through the palette per second.
+    speed: f32,
+    direction: Direction,
+    running: bool,
+    // Time of the previous frame, in milliseconds.
+    last_time: Option<f64>,
+}
 
-    out vec4 fragmentColor;
+#[wasm_bindgen]
+impl Explorer {
+    pub fn set_fractal(&self, fractal: Fractal) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        state.fractal = fractal;
 
-    vec3 Mandelbrot(vec2 c) {
-        vec2 z = c;
-        for(int i = 1; i <= iterations ; ++i) {
-            vec2 z2 = z * z;
-            if (z2.x + z2.y > 4.0) return vec3(z, float(i));
+        self.render_loop.invalidate()
+    }
 
-            z = vec2(
-                (z2.x - z2.y),
-                (z.y * z.x * 2.0)
-            ) + c;
+    // `power` is only used by `Formula::Multibrot`.
+    pub fn set_formula(&self, formula: Formula, power: f32) -> Result<(), Error> {
+        if !(power.is_finite() && (MIN_POWER..=MAX_POWER).contains(&power)) {
+            return Err(Error::InvalidArgument(format!(
+                "power must be between {MIN_POWER} and {MAX_POWER}"
+            )));
         }
-        return vec3(z, 0.);
+
+        let mut state = self.state.borrow_mut();
+        state.formula = formula;
+        state.power = power;
+
+        self.render_loop.invalidate()
     }
 
-    vec4 Colors(int i) {
-        int n = i % 16;
-        if (n ==  0) return vec4( 66.,  30.,  15., 255.) / 255.;
-        if (n ==  1) return vec4( 25.,   7.,  26., 255.) / 255.;
-        if (n ==  2) return vec4(  9.,   1.,  47., 255.) / 255.;
-        if (n ==  3) return vec4(  4.,   4.,  73., 255.) / 255.;
-        if (n ==  4) return vec4(  0.,   7., 100., 255.) / 255.;
-        if (n ==  5) return vec4( 12.,  44., 138., 255.) / 255.;
-        if (n ==  6) return vec4( 24.,  82., 177., 255.) / 255.;
-        if (n ==  7) return vec4( 57., 125., 209., 255.) / 255.;
-        if (n ==  8) return vec4(134., 181., 229., 255.) / 255.;
-        if (n ==  9) return vec4(211., 236., 248., 255.) / 255.;
-        if (n == 10) return vec4(241., 233., 191., 255.) / 255.;
-        if (n == 11) return vec4(248., 201.,  95., 255.) / 255.;
-        if (n == 12) return vec4(255., 170.,   0., 255.) / 255.;
-        if (n == 13) return vec4(204., 128.,   0., 255.) / 255.;
-        if (n == 14) return vec4(153.,  87.,   0., 255.) / 255.;
-        if (n == 15) return vec4(106,   52.,   3., 255.) / 255.;
+    // Iterates a formula such as `z^3 + sin(c)*z + c`. See `expression` for
+    // what it may contain.
+    pub fn set_custom_formula(&self, source: &str) -> Result<(), Error> {
+        let custom = CustomFormula::parse(source).map_err(Error::InvalidArgument)?;
+        let mut state = self.state.borrow_mut();
+        let next = State {
+            formula: Formula::Custom,
+            custom,
+            ..state.clone()
+        };
+        // Linked right away, so that a formula the shader compiler rejects is
+        // reported here.
+        self.renderer.prepare(&next)?;
+        *state = next;
+
+        self.render_loop.invalidate()
     }
 
-    // https://en.wikipedia.org/wiki/Plotting_algorithms_for_the_Mandelbrot_set
-    vec4 Color(int i, vec2 z) {
-        float log_zn = log(z.x * z.x + z.y * z.y) / 2.;
-        float nu = log2(log_zn / log(2.));
-        float it = float(i) + 1. - nu;
+    pub fn set_precision(&self, precision: Precision) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        state.precision = precision;
 
-        i = int(floor(it));
-        vec4 color1 = Colors(i);
-        vec4 color2 = Colors(i + 1);
-        return mix(color1, color2, fract(it));
+        self.render_loop.invalidate()
     }
 
-    void main() {
-        vec3 m = Mandelbrot(
-            vec2(
-                min.x + (max.x - min.x) * gl_FragCoord.x / resolution.x,
-            	min.y + (max.y - min.y) * gl_FragCoord.y / resolution.y
-            )
-        );
-        vec2 z = vec2(m.x, m.y);
-        int i = int(m.z);
-        fragmentColor = i == 0 ?
-            vec4(0.0, 0.0, 0.0, 1.0) :
-            Color(i, z);
+    // The parameter `c` is only used while the Julia set is rendered.
+    pub fn set_julia_parameter(&self, re: f32, im: f32) -> Result<(), Error> {
+        if !(re.is_finite() && im.is_finite()) {
+            return Err(Error::InvalidArgument(
+                "the Julia parameter must be finite".to_string(),
+            ));
+        }
+
+        let mut state = self.state.borrow_mut();
+        state.c = [re, im];
+
+        self.render_loop.invalidate()
+    }
+
+    pub fn set_palette(&self, name: &str) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        state.palette = Palette::builtin(name)
+            .ok_or_else(|| Error::InvalidArgument(format!("unknown palette `{name}`")))?;
+
+        self.render_loop.invalidate()
+    }
+
+    // Uses the first palette in a gradient file, whose format is told by the
+    // extension of `file_name`. Shared links leave it out, and keep whatever
+    // palette the page opening them has.
+    pub fn import_palette(&self, file_name: &str, text: &str) -> Result<(), Error> {
+        let palettes = gradient::parse(file_name, text).map_err(Error::InvalidArgument)?;
+        let mut state = self.state.borrow_mut();
+        state.palette = palettes
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::InvalidArgument(format!("`{file_name}` has no palettes")))?;
+
+        self.render_loop.invalidate()
+    }
+
+    pub fn set_palette_speed(&self, speed: f32) -> Result<(), Error> {
+        if !speed.is_finite() {
+            return Err(Error::InvalidArgument(
+                "the palette speed must be finite".to_string(),
+            ));
+        }
+
+        let mut state = self.state.borrow_mut();
+        state.palette_speed = speed;
+
+        self.render_loop.invalidate()
+    }
+
+    pub fn set_palette_offset(&self, offset: f32) -> Result<(), Error> {
+        if !offset.is_finite() {
+            return Err(Error::InvalidArgument(
+                "the palette offset must be finite".to_string(),
+            ));
+        }
+
+        let mut state = self.state.borrow_mut();
+        state.palette_offset = offset;
+
+        self.render_loop.invalidate()
+    }
+
+    // Averages `samples` by `samples` points per pixel, placed randomly within
+    // their cells of the grid when `jitter` is set. A single sample turns
+    // supersampling off.
+    pub fn set_supersampling(&self, samples: u32, jitter: bool) -> Result<(), Error> {
+        if !(1..=MAX_SAMPLES).contains(&samples) {
+            return Err(Error::InvalidArgument(format!(
+                "samples must be between 1 and {MAX_SAMPLES}"
+            )));
+        }
+
+        let mut state = self.state.borrow_mut();
+        state.samples = samples;
+        state.jitter = jitter;
+
+        self.render_loop.invalidate()
     }
-"#;
+
+    pub fn set_coloring(&self, coloring: Coloring) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        state.coloring = coloring;
+
+        self.render_loop.invalidate()
+    }
+
+    pub fn set_interior(&self, interior: Interior) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        state.interior = interior;
+
+        self.render_loop.invalidate()
+    }
+
+    // Places the trap used by `Coloring::Trap` at `(re, im)`. `angle` turns
+    // lines and crosses, `radius` sizes circles, and the palette spans
+    // `width` from the trap.
+    pub fn set_orbit_trap(
+        &self,
+        shape: TrapShape,
+        re: f32,
+        im: f32,
+        angle: f32,
+        radius: f32,
+        width: f32,
+    ) -> Result<(), Error> {
+        if !(width.is_finite() && width > 0.) {
+            return Err(Error::InvalidArgument(
+                "the trap width must be positive".to_string(),
+            ));
+        }
+
+        let mut state = self.state.borrow_mut();
+        state.trap = OrbitTrap {
+            shape,
+            center: [re, im],
+            angle,
+            radius,
+            width,
+        };
+
+        self.render_loop.invalidate()
+    }
+
+    pub fn start_palette_cycling(&self) -> Result<(), Error> {
+        let mut cycling = self.render_loop.cycling.borrow_mut();
+        if !cycling.running {
+            cycling.running = true;
+            cycling.last_time = None;
+        }
+
+        self.render_loop.request_frame()
+    }
+
+    // Leaves the palette where the cycling stopped.
+    pub fn stop_palette_cycling(&self) -> Result<(), Error> {
+        self.render_loop.cycling.borrow_mut().running = false;
+        let state = self.state.borrow();
+        save_hash(&state)?;
+
+        // Cycling draws a sample per pixel.
+        if state.samples > 1 {
+            self.render_loop.refine()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_palette_cycling_speed(&self, speed: f32) -> Result<(), Error> {
+        if !speed.is_finite() {
+            return Err(Error::InvalidArgument(
+                "the cycling speed must be finite".to_string(),
+            ));
+        }
+
+        self.render_loop.cycling.borrow_mut().speed = speed;
+        Ok(())
+    }
+
+    pub fn set_palette_cycling_direction(&self, direction: Direction) {
+        self.render_loop.cycling.borrow_mut().direction = direction;
+    }
+
+    // Renders the current view at `width` by `height`, which may be larger than
+    // the canvas, and returns it encoded as a PNG.
+    pub fn export_png(&self, width: u32, height: u32) -> Result<Vec<u8>, Error> {
+        let state = self.state.borrow();
+        let pixels = self.renderer.render_offscreen(&state, width, height)?;
+        export::encode_png(width, height, &pixels).map_err(|err| Error::Export(err.to_string()))
+    }
+}
 
 const VERTICES: [f32; 12] = [
     -1.0, -1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0, 1.0, -1.0, 1.0,
 ];
 
 #[wasm_bindgen]
-pub async fn start() -> Result<(), JsValue> {
-    let window = web_sys::window().ok_or_else(|| JsValue::from_str("no window exists"))?;
-    let document = window
-        .document()
-        .ok_or_else(|| JsValue::from_str("should have document"))?;
-    let body = document
-        .body()
-        .ok_or_else(|| JsValue::from_str("no body exists"))?;
-    let width = window
-        .inner_width()?
-        .as_f64()
-        .ok_or_else(|| JsValue::from_str("fail to convert inner width"))? as u32;
-    let height = window
-        .inner_height()?
-        .as_f64()
-        .ok_or_else(|| JsValue::from_str("fail to convert inner height"))? as u32;
-
-    let canvas = document
-        .create_element("canvas")?
-        .dyn_into::<HtmlCanvasElement>()?;
-    canvas.set_width(width);
-    canvas.set_height(height);
-    body.append_child(&canvas)?;
-
-    let context = canvas
-        .get_context("webgl2")?
-        .ok_or_else(|| JsValue::from_str("fail to get context"))?
-        .dyn_into::<WebGl2RenderingContext>()?;
-
-    let program = link_program(&context)?;
-
-    let uniform_min = context
-        .get_uniform_location(&program, "min")
-        .ok_or_else(|| JsValue::from_str("fali to get uniform location"))?;
-    let uniform_max = context
-        .get_uniform_location(&program, "max")
-        .ok_or_else(|| JsValue::from_str("fali to get uniform location"))?;
-    let uniform_resolution = context
-        .get_uniform_location(&program, "resolution")
-        .ok_or_else(|| JsValue::from_str("fali to get uniform location"))?;
-    let uniform_iterations = context
-        .get_uniform_location(&program, "iterations")
-        .ok_or_else(|| JsValue::from_str("fali to get uniform location"))?;
-
-    let iterations = 100;
-    let zoom = 1.8;
-    let re_center = -0.7;
-    let im_center = 0.;
-    let ratio = width as f32 / height as f32;
-    let re_min = re_center - zoom;
-    let re_max = re_center + zoom;
-    let im_min = im_center - zoom / ratio;
-    let im_max = im_center + zoom / ratio;
-
-    context.uniform2f(Some(&uniform_min), re_min, im_min);
-    context.uniform2f(Some(&uniform_max), re_max, im_max);
-    context.uniform2f(Some(&uniform_resolution), width as f32, height as f32);
-    context.uniform1i(Some(&uniform_iterations), iterations);
-
-    draw(&context, &program)?;
-
-    let iterations = Rc::new(RefCell::new(iterations));
-    let zoom = Rc::new(RefCell::new(zoom));
-    let center = Rc::new(RefCell::new(vec![re_center, im_center]));
-    let min = Rc::new(RefCell::new(vec![re_min, im_min]));
-    let max = Rc::new(RefCell::new(vec![re_max, im_max]));
-
-    on_resize(&window, &program, &context, &zoom, &center, &min, &max)?;
-
-    on_wheel(
-        &window,
-        &program,
-        &context,
-        &iterations,
-        &zoom,
-        &center,
-        &min,
-        &max,
+pub async fn start() -> Result<Explorer, Error> {
+    utils::set_panic_hook();
+
+    let window = web_sys::window().ok_or(Error::NoWindow)?;
+    let document = window.document().ok_or(Error::NoDocument)?;
+    let body = document.body().ok_or(Error::NoDocument)?;
+    let [width, height] = window_size(&window)?.map(|size| size as u32);
+
+    let mut state = State {
+        viewport: Viewport::new(
+            INITIAL_CENTER[0],
+            INITIAL_CENTER[1],
+            INITIAL_HALF_WIDTH,
+            width as f64,
+            height as f64,
+        )?,
+        iterations: INITIAL_ITERATIONS,
+        fractal: Fractal::Mandelbrot,
+        c: [-0.8, 0.156],
+        formula: Formula::Quadratic,
+        power: 2.,
+        custom: CustomFormula::default(),
+        precision: Precision::Single,
+        palette: Palette::default(),
+        palette_speed: 1.,
+        palette_offset: 0.,
+        samples: 1,
+        jitter: false,
+        coloring: Coloring::Smooth,
+        interior: Interior::Black,
+        trap: OrbitTrap::default(),
+    };
+    let hash = window.location().hash()?;
+    if !hash.is_empty() {
+        match share::decode(&hash) {
+            Ok(view) => state.restore(view),
+            Err(err) => log(&format!("ignoring the view in the URL: {err}")),
+        }
+    }
+
+    // WebGL 2 first, then WebGL 1 and at last the CPU engine, each on a canvas
+    // of its own since a canvas keeps the first kind of context it gives.
+    let new_canvas = || -> Result<HtmlCanvasElement, Error> {
+        let canvas = document
+            .create_element("canvas")?
+            .dyn_into::<HtmlCanvasElement>()
+            .map_err(JsValue::from)?;
+        canvas.set_width(width);
+        canvas.set_height(height);
+        Ok(canvas)
+    };
+    let mut renderer = None;
+    for version in [GlVersion::WebGl2, GlVersion::WebGl1] {
+        match GlRenderer::new(new_canvas()?, version) {
+            Ok(gl) => {
+                renderer = Some(Renderer::Gl(gl));
+                break;
+            }
+            Err(err) => log(&format!("falling back from {version:?}: {err}")),
+        }
+    }
+    let renderer = match renderer {
+        Some(renderer) => renderer,
+        None => Renderer::Cpu(CpuRenderer::new(new_canvas()?, &state)?),
+    };
+    body.append_child(renderer.canvas())?;
+
+    let renderer = Rc::new(renderer);
+    let state = Rc::new(RefCell::new(state));
+    let render_loop = RenderLoop::new(&renderer, &state);
+    render_loop.invalidate()?;
+
+    on_resize(&window, &render_loop, &state)?;
+
+    on_wheel(&window, &render_loop, &state)?;
+
+    on_pointer(&window, renderer.canvas(), &render_loop, &state)?;
+
+    on_keydown(&window, &render_loop, &state)?;
+
+    on_hashchange(&window, &render_loop, &state)?;
+
+    Ok(Explorer {
+        renderer,
+        state,
+        render_loop,
+    })
+}
+
+// Scales the iterations by 1.1 for every `ZOOM_IN` step in `factor`.
+fn scale_iterations(iterations: i32, factor: f64) -> i32 {
+    ((iterations as f64 * 1.1f64.powf(factor.ln() / ZOOM_IN.ln())).round() as i32)
+        .clamp(1, MAX_ITERATIONS)
+}
+
+thread_local! {
+    // The hash waiting to be written. Browsers limit how often the history can
+    // be replaced, so it is written at most once per `HASH_DELAY`.
+    static PENDING_HASH: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn save_hash(state: &State) -> Result<(), Error> {
+    let hash = format!("#{}", share::encode(&state.shared()));
+    if PENDING_HASH.replace(Some(hash)).is_some() {
+        return Ok(());
+    }
+
+    let window = web_sys::window().ok_or(Error::NoWindow)?;
+    let history = window.history()?;
+    let callback = Closure::once_into_js(move || -> Result<(), Error> {
+        if let Some(hash) = PENDING_HASH.take() {
+            history.replace_state_with_url(&JsValue::NULL, "", Some(&hash))?;
+        }
+        Ok(())
+    });
+    window.set_timeout_with_callback_and_timeout_and_arguments_0(
+        callback.unchecked_ref(),
+        HASH_DELAY,
     )?;
 
     Ok(())
 }
 
+// Draws at most once per animation frame, however often the state changes in
+// between, so that input handlers only have to mark what changed.
+struct RenderLoop {
+    renderer: Rc<Renderer>,
+    state: Rc<RefCell<State>>,
+    cycling: RefCell<Cycling>,
+    // Whether the state changed since it was last drawn.
+    dirty: Cell<bool>,
+    // What is left to draw of the view.
+    step: Cell<Option<Step>>,
+    // The requested animation frame, if any.
+    frame: Cell<Option<i32>>,
+    // Refers to the loop weakly, as the loop owns it.
+    callback: FrameCallback,
+}
+
+type FrameCallback = Closure<dyn FnMut(f64) -> Result<(), Error>>;
+
+impl RenderLoop {
+    fn new(renderer: &Rc<Renderer>, state: &Rc<RefCell<State>>) -> Rc<RenderLoop> {
+        Rc::new_cyclic(|render_loop: &Weak<RenderLoop>| {
+            let render_loop = render_loop.clone();
+            RenderLoop {
+                renderer: renderer.clone(),
+                state: state.clone(),
+                cycling: RefCell::new(Cycling {
+                    speed: CYCLING_SPEED,
+                    direction: Direction::Forward,
+                    running: false,
+                    last_time: None,
+                }),
+                dirty: Cell::new(false),
+                step: Cell::new(None),
+                frame: Cell::new(None),
+                callback: Closure::new(move |time: f64| match render_loop.upgrade() {
+                    Some(render_loop) => render_loop.frame(time),
+                    None => Ok(()),
+                }),
+            }
+        })
+    }
+
+    // Redraws the view in the next animation frame.
+    fn invalidate(&self) -> Result<(), Error> {
+        self.dirty.set(true);
+        self.request_frame()
+    }
+
+    // Draws the view again at full resolution over the one shown, without
+    // starting over from the previews.
+    fn refine(&self) -> Result<(), Error> {
+        self.step.set(Some(Step::Rows(0)));
+        self.request_frame()
+    }
+
+    fn request_frame(&self) -> Result<(), Error> {
+        if self.frame.get().is_some() {
+            return Ok(());
+        }
+
+        let window = web_sys::window().ok_or(Error::NoWindow)?;
+        let frame = window.request_animation_frame(self.callback.as_ref().unchecked_ref())?;
+        self.frame.set(Some(frame));
+
+        Ok(())
+    }
+
+    fn frame(&self, time: f64) -> Result<(), Error> {
+        self.frame.set(None);
+
+        let mut state = self.state.borrow_mut();
+        let mut cycling = self.cycling.borrow_mut();
+        if cycling.running {
+            let elapsed = cycling.last_time.map_or(0., |last_time| time - last_time);
+            cycling.last_time = Some(time);
+            let shift = cycling.speed * (elapsed / 1000.) as f32;
+            state.palette_offset = match cycling.direction {
+                Direction::Forward => state.palette_offset + shift,
+                Direction::Backward => state.palette_offset - shift,
+            }
+            .rem_euclid(1.);
+        }
+
+        let renderer = &self.renderer;
+        renderer.update_targets()?;
+        // Drawn at a sample per pixel while cycling, which is all the values
+        // of the view keep, and all there is time for where the view is drawn
+        // again every frame. The view is refined when the cycling stops.
+        let samples = if cycling.running { 1 } else { state.samples };
+        if self.dirty.take() {
+            // Starts over, dropping whatever was left of the previous view.
+            renderer.update(&state)?;
+            save_hash(&state)?;
+            self.step.set(Some(Step::Preview(0)));
+        } else if cycling.running {
+            // Only the palette offset changed, so nothing else is updated.
+            renderer.set_palette_offset(state.palette_offset);
+            if self.step.get().is_none() {
+                match &**renderer {
+                    Renderer::Gl(gl) => gl.draw_cycled(state.palette_offset)?,
+                    // Far too slow to redraw the view every frame, so the
+                    // view is refined again instead.
+                    Renderer::Cpu(_) => self.step.set(Some(Step::Rows(0))),
+                }
+            }
+        }
+
+        if let Some(step) = self.step.get() {
+            let next = match step {
+                Step::Preview(level) => {
+                    renderer.draw_preview(PREVIEW_DIVISORS[level])?;
+                    Some(if level + 1 < PREVIEW_DIVISORS.len() {
+                        Step::Preview(level + 1)
+                    } else {
+                        Step::Rows(0)
+                    })
+                }
+                Step::Rows(top) => {
+                    let height = renderer.height();
+                    let row_iterations = renderer.width() as u64
+                        * state.iterations.max(1) as u64
+                        * (samples * samples) as u64;
+                    let rows = (renderer.refine_iterations() / row_iterations.max(1))
+                        .clamp(1, height as u64) as u32;
+                    let bottom = (top + rows).min(height);
+                    renderer.draw_rows(top, bottom, samples)?;
+                    (bottom < height).then_some(Step::Rows(bottom))
+                }
+            };
+            self.step.set(next);
+        }
+        renderer.present()?;
+
+        if self.step.get().is_some() || cycling.running {
+            self.request_frame()?;
+        }
+
+        Ok(())
+    }
+}
+
+// Progressive rendering draws the view at the resolutions of
+// `PREVIEW_DIVISORS` first, and then at full resolution as many rows at a time
+// as `REFINE_ITERATIONS` allows, one step per frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Step {
+    Preview(usize),
+    // The next row to draw, counted from the top.
+    Rows(u32),
+}
+
+// Draws with WebGL where the browser has it, and with the CPU engine where it
+// does not.
+enum Renderer {
+    Gl(GlRenderer),
+    Cpu(CpuRenderer),
+}
+
+impl Renderer {
+    fn canvas(&self) -> &HtmlCanvasElement {
+        match self {
+            Renderer::Gl(gl) => &gl.canvas,
+            Renderer::Cpu(cpu) => &cpu.canvas,
+        }
+    }
+
+    fn width(&self) -> u32 {
+        match self {
+            Renderer::Gl(gl) => gl.context.drawing_buffer_width() as u32,
+            Renderer::Cpu(cpu) => cpu.size.get()[0],
+        }
+    }
+
+    fn height(&self) -> u32 {
+        match self {
+            Renderer::Gl(gl) => gl.context.drawing_buffer_height() as u32,
+            Renderer::Cpu(cpu) => cpu.size.get()[1],
+        }
+    }
+
+    fn refine_iterations(&self) -> u64 {
+        match self {
+            Renderer::Gl(_) => REFINE_ITERATIONS,
+            Renderer::Cpu(_) => CPU_REFINE_ITERATIONS,
+        }
+    }
+
+    // Makes ready to draw `state`, failing as drawing it would.
+    fn prepare(&self, state: &State) -> Result<(), Error> {
+        match self {
+            Renderer::Gl(gl) => gl.use_program(&state.shader_config(gl.version)),
+            Renderer::Cpu(_) => Ok(()),
+        }
+    }
+
+    fn update(&self, state: &State) -> Result<(), Error> {
+        match self {
+            Renderer::Gl(gl) => gl.update(state),
+            Renderer::Cpu(cpu) => {
+                *cpu.params.borrow_mut() = state.cpu_params();
+                Ok(())
+            }
+        }
+    }
+
+    fn set_palette_offset(&self, offset: f32) {
+        match self {
+            Renderer::Gl(gl) => gl.set_palette_offset(offset),
+            Renderer::Cpu(cpu) => cpu.params.borrow_mut().palette_offset = offset,
+        }
+    }
+
+    fn update_targets(&self) -> Result<(), Error> {
+        match self {
+            Renderer::Gl(gl) => gl.update_targets(),
+            Renderer::Cpu(cpu) => {
+                cpu.update_image();
+                Ok(())
+            }
+        }
+    }
+
+    fn draw_preview(&self, divisor: u32) -> Result<(), Error> {
+        match self {
+            Renderer::Gl(gl) => gl.draw_preview(divisor),
+            Renderer::Cpu(cpu) => {
+                cpu.draw_preview(divisor);
+                Ok(())
+            }
+        }
+    }
+
+    fn draw_rows(&self, top: u32, bottom: u32, samples: u32) -> Result<(), Error> {
+        match self {
+            Renderer::Gl(gl) => gl.draw_rows(top, bottom, samples),
+            Renderer::Cpu(cpu) => {
+                cpu.draw_rows(top, bottom, samples);
+                Ok(())
+            }
+        }
+    }
+
+    fn present(&self) -> Result<(), Error> {
+        match self {
+            Renderer::Gl(gl) => gl.present(),
+            Renderer::Cpu(cpu) => cpu.present(),
+        }
+    }
+
+    fn render_offscreen(&self, state: &State, width: u32, height: u32) -> Result<Vec<u8>, Error> {
+        match self {
+            Renderer::Gl(gl) => gl.render_offscreen(state, width, height),
+            Renderer::Cpu(_) => {
+                cpu::check_size(width, height)?;
+                let mut params = state.cpu_params();
+                params.viewport.resize(width as f64, height as f64);
+                Ok(cpu::render(width, height, &params))
+            }
+        }
+    }
+}
+
+// The context a canvas gave, WebGL 2 or WebGL 1. Constants are taken from
+// `WebGl2RenderingContext`, whose values for those WebGL 1 has are the same.
+pub enum GlContext {
+    WebGl1(WebGlRenderingContext),
+    WebGl2(WebGl2RenderingContext),
+}
+
+// Forwards the methods both contexts have to whichever one is in use.
+macro_rules! forward {
+    ($(fn $name:ident(&self $(, $arg:ident: $type:ty)*) $(-> $output:ty)?;)*) => {
+        impl GlContext {
+            $(
+                #[allow(clippy::too_many_arguments)]
+                pub fn $name(&self $(, $arg: $type)*) $(-> $output)? {
+                    match self {
+                        GlContext::WebGl1(context) => context.$name($($arg),*),
+                        GlContext::WebGl2(context) => context.$name($($arg),*),
+                    }
+                }
+            )*
+        }
+    };
+}
+
+forward! {
+    fn active_texture(&self, texture: u32);
+    fn attach_shader(&self, program: &WebGlProgram, shader: &WebGlShader);
+    fn bind_buffer(&self, target: u32, buffer: Option<&WebGlBuffer>);
+    fn bind_framebuffer(&self, target: u32, framebuffer: Option<&WebGlFramebuffer>);
+    fn bind_texture(&self, target: u32, texture: Option<&WebGlTexture>);
+    fn buffer_data_with_array_buffer_view(&self, target: u32, data: &js_sys::Object, usage: u32);
+    fn compile_shader(&self, shader: &WebGlShader);
+    fn create_buffer(&self) -> Option<WebGlBuffer>;
+    fn create_framebuffer(&self) -> Option<WebGlFramebuffer>;
+    fn create_program(&self) -> Option<WebGlProgram>;
+    fn create_shader(&self, shader_type: u32) -> Option<WebGlShader>;
+    fn create_texture(&self) -> Option<WebGlTexture>;
+    fn delete_framebuffer(&self, framebuffer: Option<&WebGlFramebuffer>);
+    fn delete_program(&self, program: Option<&WebGlProgram>);
+    fn delete_texture(&self, texture: Option<&WebGlTexture>);
+    fn disable(&self, capability: u32);
+    fn disable_vertex_attrib_array(&self, index: u32);
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32);
+    fn drawing_buffer_height(&self) -> i32;
+    fn drawing_buffer_width(&self) -> i32;
+    fn enable(&self, capability: u32);
+    fn enable_vertex_attrib_array(&self, index: u32);
+    fn framebuffer_texture_2d(
+        &self,
+        target: u32,
+        attachment: u32,
+        texture_target: u32,
+        texture: Option<&WebGlTexture>,
+        level: i32
+    );
+    fn get_attrib_location(&self, program: &WebGlProgram, name: &str) -> i32;
+    fn get_extension(&self, name: &str) -> Result<Option<js_sys::Object>, JsValue>;
+    fn get_parameter(&self, name: u32) -> Result<JsValue, JsValue>;
+    fn get_program_info_log(&self, program: &WebGlProgram) -> Option<String>;
+    fn get_program_parameter(&self, program: &WebGlProgram, name: u32) -> JsValue;
+    fn get_shader_info_log(&self, shader: &WebGlShader) -> Option<String>;
+    fn get_shader_parameter(&self, shader: &WebGlShader, name: u32) -> JsValue;
+    fn get_uniform_location(&self, program: &WebGlProgram, name: &str)
+        -> Option<WebGlUniformLocation>;
+    fn link_program(&self, program: &WebGlProgram);
+    fn read_pixels_with_opt_u8_array(
+        &self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        format: u32,
+        texel_type: u32,
+        pixels: Option<&mut [u8]>
+    ) -> Result<(), JsValue>;
+    fn scissor(&self, x: i32, y: i32, width: i32, height: i32);
+    fn shader_source(&self, shader: &WebGlShader, source: &str);
+    fn tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        &self,
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        border: i32,
+        format: u32,
+        texel_type: u32,
+        pixels: Option<&[u8]>
+    ) -> Result<(), JsValue>;
+    fn tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+        &self,
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        border: i32,
+        format: u32,
+        texel_type: u32,
+        pixels: Option<&js_sys::Object>
+    ) -> Result<(), JsValue>;
+    fn tex_parameteri(&self, target: u32, name: u32, value: i32);
+    fn uniform1f(&self, location: Option<&WebGlUniformLocation>, x: f32);
+    fn uniform1i(&self, location: Option<&WebGlUniformLocation>, x: i32);
+    fn uniform2f(&self, location: Option<&WebGlUniformLocation>, x: f32, y: f32);
+    fn uniform2fv_with_f32_array(&self, location: Option<&WebGlUniformLocation>, data: &[f32]);
+    fn use_program(&self, program: Option<&WebGlProgram>);
+    fn vertex_attrib_pointer_with_f64(
+        &self,
+        index: u32,
+        size: i32,
+        texel_type: u32,
+        normalized: bool,
+        stride: i32,
+        offset: f64
+    );
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32);
+}
+
+// Draws with WebGL 2, or with WebGL 1 and a shader adapted to it.
+struct GlRenderer {
+    canvas: HtmlCanvasElement,
+    context: GlContext,
+    version: GlVersion,
+    copy: CopyProgram,
+    // Only where float targets can be drawn into.
+    paint: Option<PaintProgram>,
+    // Whether the values target holds the view as shown.
+    values_drawn: Cell<bool>,
+    // Programs linked so far, by what their fragment shader was built for.
+    programs: RefCell<HashMap<ShaderConfig, Rc<Program>>>,
+    // The program in use and what it was built for.
+    program: RefCell<Rc<Program>>,
+    config: RefCell<ShaderConfig>,
+    // Set again whenever another program is used.
+    resolution: Cell<[u32; 2]>,
+    orbit: WebGlTexture,
+    // The reference orbit in `orbit` and what it was computed for.
+    reference: RefCell<Option<(OrbitKey, Vec<[f64; 2]>)>>,
+    palette: WebGlTexture,
+    // The palette uploaded to `palette`.
+    uploaded_palette: RefCell<Option<Palette>>,
+    targets: RefCell<Targets>,
+}
+
+// A linked program and where its uniforms are.
+struct Program {
+    program: WebGlProgram,
+    uniforms: Uniforms,
+}
+
+impl Program {
+    fn new(context: &GlContext, config: &ShaderConfig) -> Result<Self, Error> {
+        let program = link_program(
+            context,
+            shader::vertex_shader(config.version),
+            &shader::fragment_shader(config),
+        )?;
+        let uniforms = Uniforms::new(context, &program)?;
+        uniforms.bind_textures(context);
+
+        Ok(Program { program, uniforms })
+    }
+}
+
+// Draws a target scaled over the bound framebuffer, with `COPY_SHADER`.
+struct CopyProgram {
+    program: WebGlProgram,
+    extent: WebGlUniformLocation,
+    resolution: WebGlUniformLocation,
+}
+
+impl CopyProgram {
+    fn new(context: &GlContext) -> Result<Self, Error> {
+        let program = link_program(
+            context,
+            shader::vertex_shader(GlVersion::WebGl1),
+            shader::COPY_SHADER,
+        )?;
+        let location = |name| {
+            context
+                .get_uniform_location(&program, name)
+                .ok_or_else(|| Error::MissingUniform(name.to_string()))
+        };
+        // Targets are sampled from texture unit 2.
+        context.uniform1i(Some(&location("image")?), 2);
+
+        Ok(CopyProgram {
+            extent: location("extent")?,
+            resolution: location("resolution")?,
+            program,
+        })
+    }
+}
+
+// Paints the values target over the bound framebuffer, with
+// `shader::paint_shader()`.
+struct PaintProgram {
+    program: WebGlProgram,
+    resolution: WebGlUniformLocation,
+    palette_offset: WebGlUniformLocation,
+}
+
+impl PaintProgram {
+    // Fails unless float targets can be drawn into, which WebGL 2 only does
+    // with `EXT_color_buffer_float` and WebGL 1 not at all here.
+    fn new(context: &GlContext, version: GlVersion) -> Result<Self, Error> {
+        if version == GlVersion::WebGl1
+            || context.get_extension("EXT_color_buffer_float")?.is_none()
+        {
+            return Err(Error::Context(
+                "float targets cannot be drawn into".to_string(),
+            ));
+        }
+
+        let program = link_program(
+            context,
+            shader::vertex_shader(GlVersion::WebGl1),
+            &shader::paint_shader(),
+        )?;
+        let location = |name| {
+            context
+                .get_uniform_location(&program, name)
+                .ok_or_else(|| Error::MissingUniform(name.to_string()))
+        };
+        // Values are sampled from texture unit 2, like targets, and the
+        // palette from unit 1.
+        context.uniform1i(Some(&location("values")?), 2);
+        context.uniform1i(Some(&location("palette")?), 1);
+
+        Ok(PaintProgram {
+            resolution: location("resolution")?,
+            palette_offset: location("palette_offset")?,
+            program,
+        })
+    }
+}
+
+// What a reference orbit was computed for: the center, iterations, fractal and
+// Julia parameter.
+type OrbitKey = ([FBig; 2], i32, Fractal, [f32; 2]);
+
+impl GlRenderer {
+    fn new(canvas: HtmlCanvasElement, version: GlVersion) -> Result<Self, Error> {
+        // Antialiasing is of no use for a single quad.
+        let attributes = WebGlContextAttributes::new();
+        attributes.set_antialias(false);
+        let name = match version {
+            GlVersion::WebGl1 => "webgl",
+            GlVersion::WebGl2 => "webgl2",
+        };
+        let context = canvas
+            .get_context_with_context_options(name, &attributes)?
+            .ok_or_else(|| Error::Context(format!("{version:?} is not supported")))?;
+        let context = match version {
+            GlVersion::WebGl1 => context.dyn_into().map(GlContext::WebGl1),
+            GlVersion::WebGl2 => context.dyn_into().map(GlContext::WebGl2),
+        }
+        .map_err(|_| Error::Context(format!("the canvas gave no {version:?} context")))?;
+
+        let copy = CopyProgram::new(&context)?;
+        // Cycling the palette draws the view again every frame without it.
+        let paint = PaintProgram::new(&context, version)
+            .inspect_err(|err| log(&format!("cycling the palette without values: {err}")))
+            .ok();
+        let config = ShaderConfig {
+            version,
+            formula: Formula::Quadratic,
+            custom: String::new(),
+            precision: Precision::Single,
+            coloring: Coloring::Smooth,
+        };
+        let program = Rc::new(Program::new(&context, &config)?);
+
+        let orbit = context
+            .create_texture()
+            .ok_or_else(|| Error::Context("failed to create a texture".to_string()))?;
+        context.active_texture(WebGl2RenderingContext::TEXTURE0);
+        context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&orbit));
+        for parameter in [
+            WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+            WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+        ] {
+            context.tex_parameteri(
+                WebGl2RenderingContext::TEXTURE_2D,
+                parameter,
+                WebGl2RenderingContext::NEAREST as i32,
+            );
+        }
+
+        let palette = context
+            .create_texture()
+            .ok_or_else(|| Error::Context("failed to create a texture".to_string()))?;
+        context.active_texture(WebGl2RenderingContext::TEXTURE1);
+        context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&palette));
+        for (parameter, value) in [
+            (
+                WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+                WebGl2RenderingContext::LINEAR,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+                WebGl2RenderingContext::LINEAR,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_S,
+                WebGl2RenderingContext::REPEAT,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_T,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+        ] {
+            context.tex_parameteri(WebGl2RenderingContext::TEXTURE_2D, parameter, value as i32);
+        }
+        // Made again at the start of a frame when the canvas has been resized.
+        let targets = Targets::new(
+            &context,
+            [
+                context.drawing_buffer_width() as u32,
+                context.drawing_buffer_height() as u32,
+            ],
+            paint.is_some(),
+        )?;
+
+        Ok(GlRenderer {
+            canvas,
+            context,
+            version,
+            copy,
+            paint,
+            values_drawn: Cell::new(false),
+            programs: RefCell::new(HashMap::from([(config.clone(), program.clone())])),
+            program: RefCell::new(program),
+            config: RefCell::new(config),
+            resolution: Cell::new([0, 0]),
+            orbit,
+            reference: RefCell::new(None),
+            palette,
+            uploaded_palette: RefCell::new(None),
+            targets: RefCell::new(targets),
+        })
+    }
+
+    fn resize(&self, width: u32, height: u32) {
+        self.context.uniform2f(
+            Some(&self.uniforms().resolution),
+            width as f32,
+            height as f32,
+        );
+        self.context.viewport(0, 0, width as i32, height as i32);
+        self.resolution.set([width, height]);
+    }
+
+    fn uniforms(&self) -> Ref<'_, Uniforms> {
+        Ref::map(self.program.borrow(), |program| &program.uniforms)
+    }
+
+    // Switches to the program built for `config`, linking it unless it has
+    // been already, and sets the uniforms `update` leaves alone.
+    fn use_program(&self, config: &ShaderConfig) -> Result<(), Error> {
+        if *self.config.borrow() == *config {
+            return Ok(());
+        }
+
+        let cached = self.programs.borrow().get(config).cloned();
+        let program = match cached {
+            Some(program) => program,
+            None => {
+                let program = Rc::new(Program::new(&self.context, config)?);
+                let mut programs = self.programs.borrow_mut();
+                if programs.len() >= MAX_PROGRAMS {
+                    let current = self.config.borrow();
+                    programs.retain(|key, program| {
+                        let keep = *key == *current;
+                        if !keep {
+                            self.context.delete_program(Some(&program.program));
+                        }
+                        keep
+                    });
+                }
+                programs.insert(config.clone(), program.clone());
+                program
+            }
+        };
+        self.context.use_program(Some(&program.program));
+        *self.program.borrow_mut() = program;
+        *self.config.borrow_mut() = config.clone();
+
+        let [width, height] = self.resolution.get();
+        self.resize(width, height);
+        if let Some((_, orbit)) = &*self.reference.borrow() {
+            self.context
+                .uniform1i(self.uniforms().orbit_length.as_ref(), orbit.len() as i32);
+        }
+
+        Ok(())
+    }
+
+    fn update(&self, state: &State) -> Result<(), Error> {
+        self.use_program(&state.shader_config(self.version))?;
+        self.values_drawn.set(false);
+
+        let context = &self.context;
+        let uniforms = self.uniforms();
+
+        // Each coordinate is split into a float and the float of its remainder,
+        // so the deep zoom shader can rebuild it with double precision.
+        let [re_center, im_center] = state.viewport.center_f64().map(split);
+        let [re_scale, im_scale] = state.viewport.scale().map(split);
+        context.uniform2f(Some(&uniforms.center), re_center.0, im_center.0);
+        context.uniform2f(uniforms.center_lo.as_ref(), re_center.1, im_center.1);
+        context.uniform2f(Some(&uniforms.scale), re_scale.0, im_scale.0);
+        context.uniform2f(uniforms.scale_lo.as_ref(), re_scale.1, im_scale.1);
+        context.uniform1f(Some(&uniforms.rotation), state.viewport.rotation as f32);
+
+        context.uniform1i(Some(&uniforms.iterations), state.iterations);
+        context.uniform1i(Some(&uniforms.fractal), state.fractal as i32);
+        context.uniform2f(Some(&uniforms.c), state.c[0], state.c[1]);
+        context.uniform1f(Some(&uniforms.power), state.degree());
+        context.uniform1i(Some(&uniforms.jitter), state.jitter as i32);
+        context.uniform1i(Some(&uniforms.interior), state.interior as i32);
+
+        let trap = &state.trap;
+        let [re_direction, im_direction] = trap.direction();
+        context.uniform1i(uniforms.trap_shape.as_ref(), trap.shape as i32);
+        context.uniform2f(
+            uniforms.trap_center.as_ref(),
+            trap.center[0],
+            trap.center[1],
+        );
+        context.uniform2f(uniforms.trap_direction.as_ref(), re_direction, im_direction);
+        context.uniform1f(uniforms.trap_radius.as_ref(), trap.radius);
+        context.uniform1f(uniforms.trap_width.as_ref(), trap.width);
+
+        context.uniform1f(Some(&uniforms.palette_speed), state.palette_speed);
+        self.set_palette_offset(state.palette_offset);
+        self.update_palette(&state.palette)?;
+
+        if self.config.borrow().precision == Precision::Perturbation {
+            let (delta_scale, delta_exponent) =
+                perturbation::delta_scale(state.viewport.half_width);
+            context.uniform1f(uniforms.delta_scale.as_ref(), delta_scale);
+            context.uniform1i(uniforms.delta_exponent.as_ref(), delta_exponent);
+
+            let series = self.update_orbit(state)?;
+            context.uniform1i(uniforms.series_skip.as_ref(), series.skip);
+            context.uniform2fv_with_f32_array(
+                uniforms.series.as_ref(),
+                series.coefficients.as_flattened(),
+            );
+            context.uniform1i(uniforms.series_exponent.as_ref(), series.exponent);
+        }
+
+        Ok(())
+    }
+
+    // Computes the reference orbit again when the view has left the one it was
+    // computed for, and approximates the start of the orbits of the view
+    // relative to it.
+    fn update_orbit(&self, state: &State) -> Result<perturbation::SeriesApproximation, Error> {
+        let key = (
+            state.viewport.center.clone(),
+            state.iterations,
+            state.fractal,
+            state.c,
+        );
+        let mut reference = self.reference.borrow_mut();
+        let (_, orbit) = match reference.take() {
+            Some(cached) if cached.0 == key => reference.insert(cached),
+            _ => reference.insert((key, self.upload_orbit(state)?)),
+        };
+
+        Ok(perturbation::series_approximation(
+            orbit,
+            &state.viewport,
+            state.iterations,
+            state.fractal,
+        ))
+    }
+
+    // Computes the reference orbit of the view and uploads it to `orbit`.
+    fn upload_orbit(&self, state: &State) -> Result<Vec<[f64; 2]>, Error> {
+        let orbit = perturbation::reference_orbit(
+            &state.viewport,
+            state.iterations,
+            state.fractal,
+            state.c,
+        )?;
+        let (texels, rows) = perturbation::orbit_texels(&orbit);
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&self.orbit));
+        self.context
+            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+                WebGl2RenderingContext::TEXTURE_2D,
+                0,
+                WebGl2RenderingContext::RG32F as i32,
+                perturbation::ORBIT_WIDTH as i32,
+                rows as i32,
+                0,
+                WebGl2RenderingContext::RG,
+                WebGl2RenderingContext::FLOAT,
+                Some(&js_sys::Float32Array::from(&texels[..])),
+            )?;
+        self.context
+            .uniform1i(self.uniforms().orbit_length.as_ref(), orbit.len() as i32);
+
+        Ok(orbit)
+    }
+
+    fn set_samples(&self, samples: u32) {
+        self.context
+            .uniform1i(Some(&self.uniforms().samples), samples as i32);
+    }
+
+    fn set_palette_offset(&self, offset: f32) {
+        self.context
+            .uniform1f(Some(&self.uniforms().palette_offset), offset);
+    }
+
+    fn update_palette(&self, palette: &Palette) -> Result<(), Error> {
+        if self.uploaded_palette.borrow().as_ref() == Some(palette) {
+            return Ok(());
+        }
+
+        self.context
+            .active_texture(WebGl2RenderingContext::TEXTURE1);
+        self.context
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&self.palette));
+        self.context
+            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                WebGl2RenderingContext::TEXTURE_2D,
+                0,
+                WebGl2RenderingContext::RGBA as i32,
+                palette::PALETTE_SIZE as i32,
+                1,
+                0,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE,
+                Some(&palette.texels()),
+            )?;
+
+        *self.uploaded_palette.borrow_mut() = Some(palette.clone());
+
+        Ok(())
+    }
+
+    fn draw(&self) -> Result<(), Error> {
+        draw(&self.context, &self.program.borrow().program)
+    }
+
+    // Draws the view into a texture of the given size instead of the canvas,
+    // and reads it back as RGBA rows from the top.
+    fn render_offscreen(&self, state: &State, width: u32, height: u32) -> Result<Vec<u8>, Error> {
+        let context = &self.context;
+        let max_size = context
+            .get_parameter(WebGl2RenderingContext::MAX_TEXTURE_SIZE)?
+            .as_f64()
+            .unwrap_or(0.) as u32;
+        if width == 0 || height == 0 || width > max_size || height > max_size {
+            return Err(Error::InvalidArgument(format!(
+                "image size must be between 1 and {max_size}"
+            )));
+        }
+
+        // Sizes past what a 32-bit address space holds pass the check above.
+        let size = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|size| size.checked_mul(4))
+            .ok_or_else(|| Error::InvalidArgument(format!("{width} by {height} is too large")))?;
+
+        let target = Target::new(context, width, height)?;
+
+        let mut pixels = vec![0; size];
+        let mut export = state.clone();
+        export.viewport.resize(width as f64, height as f64);
+        context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(&target.framebuffer),
+        );
+        self.resize(width, height);
+        self.set_samples(export.samples);
+        let result = self
+            .update(&export)
+            .and_then(|_| self.draw())
+            .and_then(|_| {
+                context.read_pixels_with_opt_u8_array(
+                    0,
+                    0,
+                    width as i32,
+                    height as i32,
+                    WebGl2RenderingContext::RGBA,
+                    WebGl2RenderingContext::UNSIGNED_BYTE,
+                    Some(&mut pixels),
+                )?;
+                Ok(())
+            });
+
+        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        target.delete(context);
+        self.resize(
+            context.drawing_buffer_width() as u32,
+            context.drawing_buffer_height() as u32,
+        );
+        self.update(state)?;
+        result?;
+
+        // WebGL reads rows from the bottom.
+        Ok(pixels
+            .chunks_exact(width as usize * 4)
+            .rev()
+            .flatten()
+            .copied()
+            .collect())
+    }
+
+    // Makes the targets again when the canvas has been resized.
+    fn update_targets(&self) -> Result<(), Error> {
+        let context = &self.context;
+        let size = [
+            context.drawing_buffer_width() as u32,
+            context.drawing_buffer_height() as u32,
+        ];
+        if self.targets.borrow().size == size {
+            return Ok(());
+        }
+
+        let targets = Targets::new(context, size, self.paint.is_some())?;
+        self.targets.replace(targets).delete(context);
+        self.values_drawn.set(false);
+
+        Ok(())
+    }
+
+    // Draws the view at `1 / divisor` of the resolution and scales it up into
+    // the image.
+    fn draw_preview(&self, divisor: u32) -> Result<(), Error> {
+        let context = &self.context;
+        let targets = self.targets.borrow();
+        let Targets {
+            size: [width, height],
+            image,
+            preview,
+            ..
+        } = &*targets;
+        let (preview_width, preview_height) = ((width / divisor).max(1), (height / divisor).max(1));
+
+        context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(&preview.framebuffer),
+        );
+        self.resize(preview_width, preview_height);
+        self.set_samples(1);
+        self.draw()?;
+
+        context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(&image.framebuffer),
+        );
+        self.copy(
+            preview,
+            [
+                preview_width as f32 / *width as f32,
+                preview_height as f32 / *height as f32,
+            ],
+            [*width, *height],
+        )
+    }
+
+    // Draws the rows from `top` to `bottom`, counted from the top, into the
+    // image at full resolution.
+    fn draw_rows(&self, top: u32, bottom: u32, samples: u32) -> Result<(), Error> {
+        let context = &self.context;
+        let targets = self.targets.borrow();
+        let Targets {
+            size: [width, height],
+            image,
+            ..
+        } = &*targets;
+
+        context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(&image.framebuffer),
+        );
+        self.resize(*width, *height);
+        self.set_samples(samples);
+        context.enable(WebGl2RenderingContext::SCISSOR_TEST);
+        context.scissor(
+            0,
+            (height - bottom) as i32,
+            *width as i32,
+            (bottom - top) as i32,
+        );
+        let result = self.draw();
+        context.disable(WebGl2RenderingContext::SCISSOR_TEST);
+
+        result
+    }
+
+    // Draws the view into the image again with the palette at `offset`. The
+    // values of the view are drawn once and only painted after that, unless
+    // there is no values target to keep them in.
+    fn draw_cycled(&self, offset: f32) -> Result<(), Error> {
+        let context = &self.context;
+        let targets = self.targets.borrow();
+        let Targets {
+            size: [width, height],
+            image,
+            values,
+            ..
+        } = &*targets;
+        let (Some(paint), Some(values)) = (&self.paint, values) else {
+            let height = *height;
+            drop(targets);
+            return self.draw_rows(0, height, 1);
+        };
+
+        if !self.values_drawn.replace(true) {
+            context.bind_framebuffer(
+                WebGl2RenderingContext::FRAMEBUFFER,
+                Some(&values.framebuffer),
+            );
+            self.resize(*width, *height);
+            context.uniform1i(Some(&self.uniforms().values), 1);
+            let result = self.draw();
+            context.uniform1i(Some(&self.uniforms().values), 0);
+            result.inspect_err(|_| self.values_drawn.set(false))?;
+        }
+
+        context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(&image.framebuffer),
+        );
+        context.use_program(Some(&paint.program));
+        context.active_texture(WebGl2RenderingContext::TEXTURE2);
+        context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&values.texture));
+        context.uniform2f(Some(&paint.resolution), *width as f32, *height as f32);
+        context.uniform1f(Some(&paint.palette_offset), offset);
+        context.viewport(0, 0, *width as i32, *height as i32);
+        let result = draw(context, &paint.program);
+        context.use_program(Some(&self.program.borrow().program));
+
+        result
+    }
+
+    // Copies the image to the canvas.
+    fn present(&self) -> Result<(), Error> {
+        let targets = self.targets.borrow();
+        let Targets {
+            size: [width, height],
+            image,
+            ..
+        } = &*targets;
+
+        self.context
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        self.copy(image, [1., 1.], [*width, *height])
+    }
+
+    // Draws the lower left `extent` of `source`, as a fraction of its size,
+    // over the bound framebuffer of the given size, and goes back to the
+    // program in use.
+    fn copy(&self, source: &Target, extent: [f32; 2], size: [u32; 2]) -> Result<(), Error> {
+        let context = &self.context;
+        let copy = &self.copy;
+        context.use_program(Some(&copy.program));
+        context.active_texture(WebGl2RenderingContext::TEXTURE2);
+        context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&source.texture));
+        context.uniform2f(Some(&copy.extent), extent[0], extent[1]);
+        context.uniform2f(Some(&copy.resolution), size[0] as f32, size[1] as f32);
+        context.viewport(0, 0, size[0] as i32, size[1] as i32);
+        let result = draw(context, &copy.program);
+        context.use_program(Some(&self.program.borrow().program));
+
+        result
+    }
+}
+
+// Draws with the CPU engine into a 2D canvas, in single precision whatever the
+// precision asked for.
+struct CpuRenderer {
+    canvas: HtmlCanvasElement,
+    context: CanvasRenderingContext2d,
+    params: RefCell<cpu::Params>,
+    size: Cell<[u32; 2]>,
+    // The view as shown, refined over several frames, as RGBA rows from the
+    // top.
+    image: RefCell<Vec<u8>>,
+}
+
+impl CpuRenderer {
+    fn new(canvas: HtmlCanvasElement, state: &State) -> Result<Self, Error> {
+        let context = canvas
+            .get_context("2d")?
+            .and_then(|context| context.dyn_into::<CanvasRenderingContext2d>().ok())
+            .ok_or_else(|| Error::Context("the canvas has no 2D context".to_string()))?;
+
+        Ok(CpuRenderer {
+            canvas,
+            context,
+            params: RefCell::new(state.cpu_params()),
+            size: Cell::new([0, 0]),
+            image: RefCell::new(Vec::new()),
+        })
+    }
+
+    // Makes the image again when the canvas has been resized.
+    fn update_image(&self) {
+        let size = [self.canvas.width(), self.canvas.height()];
+        if self.size.get() != size {
+            self.size.set(size);
+            *self.image.borrow_mut() = vec![0; size[0] as usize * size[1] as usize * 4];
+        }
+    }
+
+    // Draws the view at `1 / divisor` of the resolution and scales it up into
+    // the image.
+    fn draw_preview(&self, divisor: u32) {
+        let [width, height] = self.size.get();
+        // A canvas with no pixels has no rows to draw.
+        if width == 0 || height == 0 {
+            return;
+        }
+        let (preview_width, preview_height) = ((width / divisor).max(1), (height / divisor).max(1));
+        let mut params = self.params.borrow_mut();
+        params.samples = 1;
+        let preview = cpu::render(preview_width, preview_height, &params);
+
+        let mut image = self.image.borrow_mut();
+        for (y, row) in image.chunks_exact_mut(width as usize * 4).enumerate() {
+            let preview_y = (y as u32 * preview_height / height) as usize;
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let preview_x = (x as u32 * preview_width / width) as usize;
+                let start = (preview_y * preview_width as usize + preview_x) * 4;
+                pixel.copy_from_slice(&preview[start..start + 4]);
+            }
+        }
+    }
+
+    // Draws the rows from `top` to `bottom`, counted from the top, into the
+    // image at full resolution.
+    fn draw_rows(&self, top: u32, bottom: u32, samples: u32) {
+        let [width, height] = self.size.get();
+        if width == 0 || height == 0 {
+            return;
+        }
+        let mut params = self.params.borrow_mut();
+        params.samples = samples;
+        let rows = cpu::render_rows(width, height, top..bottom, &params);
+
+        let row_size = width as usize * 4;
+        self.image.borrow_mut()[top as usize * row_size..bottom as usize * row_size]
+            .copy_from_slice(&rows);
+    }
+
+    fn present(&self) -> Result<(), Error> {
+        let [width, height] = self.size.get();
+        // `ImageData` cannot be empty.
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let image = self.image.borrow();
+        let data = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&image), width, height)?;
+        self.context.put_image_data(&data, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+// Offscreen images the size of the canvas.
+struct Targets {
+    size: [u32; 2],
+    // The view as shown, refined over several frames.
+    image: Target,
+    // Low resolution drawings of the view, in its lower left corner.
+    preview: Target,
+    // The values of the view at a sample per pixel, painted with the palette
+    // at each offset while it cycles.
+    values: Option<Target>,
+}
+
+impl Targets {
+    fn new(context: &GlContext, size: [u32; 2], values: bool) -> Result<Self, Error> {
+        let [width, height] = size;
+        Ok(Targets {
+            size,
+            image: Target::new(context, width, height)?,
+            preview: Target::new(context, width, height)?,
+            values: values
+                .then(|| Target::values(context, width, height))
+                .transpose()?,
+        })
+    }
+
+    fn delete(&self, context: &GlContext) {
+        self.image.delete(context);
+        self.preview.delete(context);
+        if let Some(values) = &self.values {
+            values.delete(context);
+        }
+    }
+}
+
+// A texture to draw into.
+struct Target {
+    texture: WebGlTexture,
+    framebuffer: WebGlFramebuffer,
+}
+
+impl Target {
+    fn new(context: &GlContext, width: u32, height: u32) -> Result<Self, Error> {
+        Target::with_format(
+            context,
+            [width, height],
+            // WebGL 1 only takes the unsized format.
+            WebGl2RenderingContext::RGBA,
+            WebGl2RenderingContext::UNSIGNED_BYTE,
+            WebGl2RenderingContext::LINEAR,
+        )
+    }
+
+    // A target of floats, read texel by texel since WebGL 2 only filters
+    // them with another extension.
+    fn values(context: &GlContext, width: u32, height: u32) -> Result<Self, Error> {
+        Target::with_format(
+            context,
+            [width, height],
+            WebGl2RenderingContext::RGBA32F,
+            WebGl2RenderingContext::FLOAT,
+            WebGl2RenderingContext::NEAREST,
+        )
+    }
+
+    fn with_format(
+        context: &GlContext,
+        [width, height]: [u32; 2],
+        internal_format: u32,
+        texel_type: u32,
+        filter: u32,
+    ) -> Result<Self, Error> {
+        let texture = context
+            .create_texture()
+            .ok_or_else(|| Error::Context("failed to create a texture".to_string()))?;
+        // Units 0 and 1 hold the reference orbit and the palette.
+        context.active_texture(WebGl2RenderingContext::TEXTURE2);
+        context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+        context.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            WebGl2RenderingContext::TEXTURE_2D,
+            0,
+            internal_format as i32,
+            width as i32,
+            height as i32,
+            0,
+            WebGl2RenderingContext::RGBA,
+            texel_type,
+            None,
+        )?;
+        // WebGL 1 only samples textures of any size without mipmaps and
+        // clamped to the edge.
+        for (parameter, value) in [
+            (WebGl2RenderingContext::TEXTURE_MIN_FILTER, filter),
+            (WebGl2RenderingContext::TEXTURE_MAG_FILTER, filter),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_S,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_T,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+        ] {
+            context.tex_parameteri(WebGl2RenderingContext::TEXTURE_2D, parameter, value as i32);
+        }
+        let framebuffer = context
+            .create_framebuffer()
+            .ok_or_else(|| Error::Context("failed to create a framebuffer".to_string()))?;
+        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&framebuffer));
+        context.framebuffer_texture_2d(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            WebGl2RenderingContext::COLOR_ATTACHMENT0,
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&texture),
+            0,
+        );
+        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+
+        Ok(Target {
+            texture,
+            framebuffer,
+        })
+    }
+
+    fn delete(&self, context: &GlContext) {
+        context.delete_framebuffer(Some(&self.framebuffer));
+        context.delete_texture(Some(&self.texture));
+    }
+}
+
+// The optional uniforms are only in the programs built with the pieces that
+// use them.
+struct Uniforms {
+    center: WebGlUniformLocation,
+    center_lo: Option<WebGlUniformLocation>,
+    scale: WebGlUniformLocation,
+    scale_lo: Option<WebGlUniformLocation>,
+    rotation: WebGlUniformLocation,
+    resolution: WebGlUniformLocation,
+    iterations: WebGlUniformLocation,
+    fractal: WebGlUniformLocation,
+    c: WebGlUniformLocation,
+    power: WebGlUniformLocation,
+    orbit: Option<WebGlUniformLocation>,
+    orbit_length: Option<WebGlUniformLocation>,
+    delta_scale: Option<WebGlUniformLocation>,
+    delta_exponent: Option<WebGlUniformLocation>,
+    series_skip: Option<WebGlUniformLocation>,
+    series: Option<WebGlUniformLocation>,
+    series_exponent: Option<WebGlUniformLocation>,
+    palette: WebGlUniformLocation,
+    palette_speed: WebGlUniformLocation,
+    palette_offset: WebGlUniformLocation,
+    samples: WebGlUniformLocation,
+    jitter: WebGlUniformLocation,
+    values: WebGlUniformLocation,
+    interior: WebGlUniformLocation,
+    trap_shape: Option<WebGlUniformLocation>,
+    trap_center: Option<WebGlUniformLocation>,
+    trap_direction: Option<WebGlUniformLocation>,
+    trap_radius: Option<WebGlUniformLocation>,
+    trap_width: Option<WebGlUniformLocation>,
+}
+
+impl Uniforms {
+    fn new(context: &GlContext, program: &WebGlProgram) -> Result<Self, Error> {
+        let location = |name| {
+            context
+                .get_uniform_location(program, name)
+                .ok_or_else(|| Error::MissingUniform(name.to_string()))
+        };
+        Ok(Uniforms {
+            center: location("center")?,
+            center_lo: context.get_uniform_location(program, "center_lo"),
+            scale: location("scale")?,
+            scale_lo: context.get_uniform_location(program, "scale_lo"),
+            rotation: location("rotation")?,
+            resolution: location("resolution")?,
+            iterations: location("iterations")?,
+            fractal: location("fractal")?,
+            c: location("c")?,
+            power: location("power")?,
+            orbit: context.get_uniform_location(program, "orbit"),
+            orbit_length: context.get_uniform_location(program, "orbit_length"),
+            delta_scale: context.get_uniform_location(program, "delta_scale"),
+            delta_exponent: context.get_uniform_location(program, "delta_exponent"),
+            series_skip: context.get_uniform_location(program, "series_skip"),
+            series: context.get_uniform_location(program, "series"),
+            series_exponent: context.get_uniform_location(program, "series_exponent"),
+            palette: location("palette")?,
+            palette_speed: location("palette_speed")?,
+            palette_offset: location("palette_offset")?,
+            samples: location("samples")?,
+            jitter: location("jitter")?,
+            values: location("values")?,
+            interior: location("interior")?,
+            trap_shape: context.get_uniform_location(program, "trap_shape"),
+            trap_center: context.get_uniform_location(program, "trap_center"),
+            trap_direction: context.get_uniform_location(program, "trap_direction"),
+            trap_radius: context.get_uniform_location(program, "trap_radius"),
+            trap_width: context.get_uniform_location(program, "trap_width"),
+        })
+    }
+
+    // Samples the orbit from texture unit 0 and the palette from unit 1.
+    fn bind_textures(&self, context: &GlContext) {
+        context.uniform1i(self.orbit.as_ref(), 0);
+        context.uniform1i(Some(&self.palette), 1);
+    }
+}
+
+// The size of the window's viewport, in CSS pixels.
+fn window_size(window: &Window) -> Result<[f64; 2], Error> {
+    let size = [window.inner_width()?, window.inner_height()?].map(|size| size.as_f64());
+    match size {
+        [Some(width), Some(height)] => Ok([width, height]),
+        _ => Err(Error::Window("the window size is not a number".to_string())),
+    }
+}
+
+fn split(x: f64) -> (f32, f32) {
+    let hi = x as f32;
+    (hi, (x - hi as f64) as f32)
+}
+
 fn on_resize(
     window: &Window,
-    program: &WebGlProgram,
-    context: &WebGl2RenderingContext,
-    zoom: &Rc<RefCell<f32>>,
-    center: &Rc<RefCell<Vec<f32>>>,
-    min: &Rc<RefCell<Vec<f32>>>,
-    max: &Rc<RefCell<Vec<f32>>>,
-) -> Result<(), JsValue> {
+    render_loop: &Rc<RenderLoop>,
+    state: &Rc<RefCell<State>>,
+) -> Result<(), Error> {
     let new_window = window.clone();
-    let context = context.clone();
-    let program = program.clone();
-    let canvas = context
-        .canvas()
-        .unwrap_throw()
-        .dyn_into::<HtmlCanvasElement>()?;
-    let uniform_min = context
-        .get_uniform_location(&program, "min")
-        .ok_or_else(|| JsValue::from_str("fali to get uniform location"))?;
-    let uniform_max = context
-        .get_uniform_location(&program, "max")
-        .ok_or_else(|| JsValue::from_str("fali to get uniform location"))?;
-    let uniform_resolution = context
-        .get_uniform_location(&program, "resolution")
-        .ok_or_else(|| JsValue::from_str("fali to get uniform location"))?;
-    let zoom = zoom.clone();
-    let center = center.clone();
-    let min = min.clone();
-    let max = max.clone();
-    let closure = Closure::<dyn FnMut()>::new(move || {
-        let width = new_window
-            .inner_width()
-            .unwrap_throw()
-            .as_f64()
-            .ok_or_else(|| JsValue::from_str("fail to convert inner width"))
-            .unwrap_throw() as u32;
-        let height = new_window
-            .inner_height()
-            .unwrap_throw()
-            .as_f64()
-            .ok_or_else(|| JsValue::from_str("fail to convert inner height"))
-            .unwrap_throw() as u32;
+    let render_loop = render_loop.clone();
+    let canvas = render_loop.renderer.canvas().clone();
+    let state = state.clone();
+    let closure = Closure::<dyn FnMut() -> Result<(), Error>>::new(move || {
+        let [width, height] = window_size(&new_window)?.map(|size| size as u32);
 
         canvas.set_width(width);
         canvas.set_height(height);
 
-        let ratio = width as f32 / height as f32;
-
-        let zoom = zoom.borrow();
-        let center = center.borrow();
-        let re_center = center.first().unwrap_throw();
-        let im_center = center.last().unwrap_throw();
-        let re_min = re_center - *zoom;
-        let re_max = re_center + *zoom;
-        let im_min = im_center - *zoom / ratio;
-        let im_max = im_center + *zoom / ratio;
-        *min.borrow_mut() = vec![re_min, im_min];
-        *max.borrow_mut() = vec![re_max, im_max];
+        let mut state = state.borrow_mut();
+        state.viewport.resize(width as f64, height as f64);
 
-        context.uniform2f(Some(&uniform_min), re_min, im_min);
-        context.uniform2f(Some(&uniform_max), re_max, im_max);
-        context.uniform2f(Some(&uniform_resolution), width as f32, height as f32);
-
-        context.viewport(0, 0, width as i32, height as i32);
-
-        draw(&context, &program).unwrap_throw();
+        render_loop.invalidate()
     });
     window.set_onresize(Some(closure.as_ref().unchecked_ref()));
     closure.forget();
@@ -265,81 +2029,26 @@ fn on_resize(
 
 fn on_wheel(
     window: &Window,
-    program: &WebGlProgram,
-    context: &WebGl2RenderingContext,
-    iterations: &Rc<RefCell<i32>>,
-    zoom: &Rc<RefCell<f32>>,
-    center: &Rc<RefCell<Vec<f32>>>,
-    min: &Rc<RefCell<Vec<f32>>>,
-    max: &Rc<RefCell<Vec<f32>>>,
-) -> Result<(), JsValue> {
+    render_loop: &Rc<RenderLoop>,
+    state: &Rc<RefCell<State>>,
+) -> Result<(), Error> {
     let new_window = window.clone();
-    let context = context.clone();
-    let program = program.clone();
-    let uniform_min = context
-        .get_uniform_location(&program, "min")
-        .ok_or_else(|| JsValue::from_str("fali to get uniform location"))?;
-    let uniform_max = context
-        .get_uniform_location(&program, "max")
-        .ok_or_else(|| JsValue::from_str("fali to get uniform location"))?;
-    let uniform_iterations = context
-        .get_uniform_location(&program, "iterations")
-        .ok_or_else(|| JsValue::from_str("fali to get uniform location"))?;
-    let iterations = iterations.clone();
-    let zoom = zoom.clone();
-    let center = center.clone();
-    let min = min.clone();
-    let max = max.clone();
-    let closure = Closure::<dyn FnMut(_)>::new(move |event: WheelEvent| {
-        let width = new_window
-            .inner_width()
-            .unwrap_throw()
-            .as_f64()
-            .ok_or_else(|| JsValue::from_str("fail to convert inner width"))
-            .unwrap_throw() as f32;
-        let height = new_window
-            .inner_height()
-            .unwrap_throw()
-            .as_f64()
-            .ok_or_else(|| JsValue::from_str("fail to convert inner height"))
-            .unwrap_throw() as f32;
+    let render_loop = render_loop.clone();
+    let state = state.clone();
+    let closure = Closure::<dyn FnMut(_) -> Result<(), Error>>::new(move |event: WheelEvent| {
+        let [width, height] = window_size(&new_window)?;
 
         let zoom_flag = event.delta_y() < 0.;
-        let ratio = width / height;
-
-        let mut iterations = iterations.borrow_mut();
-        let mut zoom = zoom.borrow_mut();
-        let mut center = center.borrow_mut();
-        let mut re_center = *center.first().unwrap_throw();
-        let mut im_center = *center.last().unwrap_throw();
-        if zoom_flag {
-            *iterations = (*iterations as f32 * 1.1).round() as i32;
-            re_center +=
-                (event.client_x() as f32 - (width / 2.)) / (width / 2.) * (*zoom * (1. - ZOOM_IN));
-            im_center -= (event.client_y() as f32 - (height / 2.)) / (height / 2.)
-                * (*zoom * (1. - ZOOM_IN));
-        }
-        *zoom *= if zoom_flag { ZOOM_IN } else { 1. / ZOOM_IN };
-        if !zoom_flag {
-            *iterations = (*iterations as f32 / 1.1).round() as i32;
-            re_center -=
-                (event.client_x() as f32 - (width / 2.)) / (width / 2.) * (*zoom * (1. - ZOOM_IN));
-            im_center += (event.client_y() as f32 - (height / 2.)) / (height / 2.)
-                * (*zoom * (1. - ZOOM_IN));
-        }
-        let re_min = re_center - *zoom;
-        let re_max = re_center + *zoom;
-        let im_min = im_center - *zoom / ratio;
-        let im_max = im_center + *zoom / ratio;
-        *min.borrow_mut() = vec![re_min, im_min];
-        *max.borrow_mut() = vec![re_max, im_max];
-        *center = vec![re_center, im_center];
-
-        context.uniform2f(Some(&uniform_min), re_min, im_min);
-        context.uniform2f(Some(&uniform_max), re_max, im_max);
-        context.uniform1i(Some(&uniform_iterations), *iterations);
-
-        draw(&context, &program).unwrap_throw();
+
+        let factor = if zoom_flag { ZOOM_IN } else { 1. / ZOOM_IN };
+
+        let mut state = state.borrow_mut();
+        state.iterations = scale_iterations(state.iterations, factor);
+        state
+            .viewport
+            .zoom_at(factor, event.client_x(), event.client_y(), width, height)?;
+
+        render_loop.invalidate()
     });
     window.set_onwheel(Some(closure.as_ref().unchecked_ref()));
     closure.forget();
@@ -347,14 +2056,232 @@ fn on_wheel(
     Ok(())
 }
 
-fn draw(context: &WebGl2RenderingContext, program: &WebGlProgram) -> Result<(), JsValue> {
+// Listens on the canvas rather than the window, so that drags on the controls
+// of the page are left to them. The canvas captures the pointers pressed on
+// it, so that drags leaving it still end.
+fn on_pointer(
+    window: &Window,
+    canvas: &HtmlCanvasElement,
+    render_loop: &Rc<RenderLoop>,
+    state: &Rc<RefCell<State>>,
+) -> Result<(), Error> {
+    // The ids and last positions of the pressed pointers. One pointer pans the
+    // view and two pointers pinch it.
+    let pointers = Rc::new(RefCell::new(Vec::<(i32, [f64; 2])>::new()));
+    // The iterations and half width when the pinch started.
+    let pinch = Rc::new(RefCell::new(None::<(i32, f64)>));
+
+    let new_canvas = canvas.clone();
+    let new_pointers = pointers.clone();
+    let new_pinch = pinch.clone();
+    let new_state = state.clone();
+    let closure = Closure::<dyn FnMut(_) -> Result<(), Error>>::new(move |event: PointerEvent| {
+        let mut pointers = new_pointers.borrow_mut();
+        if event.button() != 0 || pointers.len() >= 2 {
+            return Ok(());
+        }
+
+        new_canvas.set_pointer_capture(event.pointer_id())?;
+        pointers.push((event.pointer_id(), [event.client_x(), event.client_y()]));
+        if pointers.len() == 2 {
+            let state = new_state.borrow();
+            *new_pinch.borrow_mut() = Some((state.iterations, state.viewport.half_width));
+        }
+        Ok(())
+    });
+    canvas.set_onpointerdown(Some(closure.as_ref().unchecked_ref()));
+    closure.forget();
+
+    let new_window = window.clone();
+    let render_loop = render_loop.clone();
+    let state = state.clone();
+    let new_pointers = pointers.clone();
+    let new_pinch = pinch.clone();
+    let closure = Closure::<dyn FnMut(_) -> Result<(), Error>>::new(move |event: PointerEvent| {
+        // A mouse or pen moving with no button held.
+        if event.buttons() == 0 {
+            return Ok(());
+        }
+
+        let mut pointers = new_pointers.borrow_mut();
+        let Some(index) = pointers
+            .iter()
+            .position(|&(pointer_id, _)| pointer_id == event.pointer_id())
+        else {
+            return Ok(());
+        };
+
+        let [width, height] = window_size(&new_window)?;
+
+        let last = pointers.clone();
+        pointers[index].1 = [event.client_x(), event.client_y()];
+
+        let mut state = state.borrow_mut();
+        match (&last[..], &pointers[..]) {
+            ([(_, last)], [(_, position)]) => {
+                state
+                    .viewport
+                    .pan(position[0] - last[0], position[1] - last[1], width, height)?;
+            }
+            ([(_, last_a), (_, last_b)], [(_, a), (_, b)]) => {
+                let mid = [(a[0] + b[0]) / 2., (a[1] + b[1]) / 2.];
+                let last_mid = [(last_a[0] + last_b[0]) / 2., (last_a[1] + last_b[1]) / 2.];
+                let distance = (a[0] - b[0]).hypot(a[1] - b[1]);
+                let last_distance = (last_a[0] - last_b[0]).hypot(last_a[1] - last_b[1]);
+                if distance == 0. || last_distance == 0. {
+                    return Ok(());
+                }
+
+                state
+                    .viewport
+                    .pan(mid[0] - last_mid[0], mid[1] - last_mid[1], width, height)?;
+                state
+                    .viewport
+                    .zoom_at(last_distance / distance, mid[0], mid[1], width, height)?;
+                if let Some((start_iterations, start_half_width)) = *new_pinch.borrow() {
+                    state.iterations = scale_iterations(
+                        start_iterations,
+                        state.viewport.half_width / start_half_width,
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        render_loop.invalidate()
+    });
+    canvas.set_onpointermove(Some(closure.as_ref().unchecked_ref()));
+    closure.forget();
+
+    let closure = Closure::<dyn FnMut(_)>::new(move |event: PointerEvent| {
+        let mut pointers = pointers.borrow_mut();
+        pointers.retain(|&(pointer_id, _)| pointer_id != event.pointer_id());
+        if pointers.len() < 2 {
+            *pinch.borrow_mut() = None;
+        }
+    });
+    canvas.set_onpointerup(Some(closure.as_ref().unchecked_ref()));
+    canvas.set_onpointercancel(Some(closure.as_ref().unchecked_ref()));
+    closure.forget();
+
+    Ok(())
+}
+
+// Whether a key goes to a text field or editable element of the page, and so
+// is typed rather than taken as a shortcut.
+fn is_editing(event: &KeyboardEvent) -> bool {
+    event
+        .target()
+        .and_then(|target| target.dyn_into::<HtmlElement>().ok())
+        .is_some_and(|element| {
+            element.is_content_editable()
+                || matches!(element.tag_name().as_str(), "INPUT" | "TEXTAREA" | "SELECT")
+        })
+}
+
+fn on_keydown(
+    window: &Window,
+    render_loop: &Rc<RenderLoop>,
+    state: &Rc<RefCell<State>>,
+) -> Result<(), Error> {
+    let new_window = window.clone();
+    let render_loop = render_loop.clone();
+    let state = state.clone();
+    let closure = Closure::<dyn FnMut(_) -> Result<(), Error>>::new(move |event: KeyboardEvent| {
+        if event.ctrl_key() || event.meta_key() || event.alt_key() || is_editing(&event) {
+            return Ok(());
+        }
+
+        let [width, height] = window_size(&new_window)?;
+
+        let mut state = state.borrow_mut();
+        let State {
+            viewport,
+            iterations,
+            ..
+        } = &mut *state;
+        match event.key().as_str() {
+            "ArrowUp" | "w" | "W" => viewport.pan(0., height * PAN_STEP, width, height)?,
+            "ArrowDown" | "s" | "S" => viewport.pan(0., -height * PAN_STEP, width, height)?,
+            "ArrowLeft" | "a" | "A" => viewport.pan(width * PAN_STEP, 0., width, height)?,
+            "ArrowRight" | "d" | "D" => viewport.pan(-width * PAN_STEP, 0., width, height)?,
+            "+" | "=" => {
+                *iterations = scale_iterations(*iterations, ZOOM_IN);
+                viewport.zoom_at(ZOOM_IN, width / 2., height / 2., width, height)?;
+            }
+            "-" | "_" => {
+                *iterations = scale_iterations(*iterations, 1. / ZOOM_IN);
+                viewport.zoom_at(1. / ZOOM_IN, width / 2., height / 2., width, height)?;
+            }
+            "]" => {
+                *iterations = ((*iterations as f64 * 1.1).round() as i32)
+                    .max(*iterations + 1)
+                    .min(MAX_ITERATIONS);
+            }
+            "[" => {
+                *iterations = ((*iterations as f64 / 1.1).round() as i32)
+                    .min(*iterations - 1)
+                    .max(1);
+            }
+            "r" | "R" | "Home" => {
+                *iterations = INITIAL_ITERATIONS;
+                *viewport = Viewport::new(
+                    INITIAL_CENTER[0],
+                    INITIAL_CENTER[1],
+                    INITIAL_HALF_WIDTH,
+                    width,
+                    height,
+                )?;
+            }
+            _ => return Ok(()),
+        }
+        event.prevent_default();
+
+        render_loop.invalidate()
+    });
+    window.set_onkeydown(Some(closure.as_ref().unchecked_ref()));
+    closure.forget();
+
+    Ok(())
+}
+
+// Follows links to other views opened in the same page.
+fn on_hashchange(
+    window: &Window,
+    render_loop: &Rc<RenderLoop>,
+    state: &Rc<RefCell<State>>,
+) -> Result<(), Error> {
+    let new_window = window.clone();
+    let render_loop = render_loop.clone();
+    let state = state.clone();
+    let closure = Closure::<dyn FnMut() -> Result<(), Error>>::new(move || {
+        let hash = new_window.location().hash()?;
+        let view = match share::decode(&hash) {
+            Ok(view) => view,
+            Err(err) => {
+                log(&format!("ignoring the view in the URL: {err}"));
+                return Ok(());
+            }
+        };
+
+        let mut state = state.borrow_mut();
+        state.restore(view);
+        render_loop.invalidate()
+    });
+    window.set_onhashchange(Some(closure.as_ref().unchecked_ref()));
+    closure.forget();
+
+    Ok(())
+}
+
+fn draw(context: &GlContext, program: &WebGlProgram) -> Result<(), Error> {
     // context.clear_color(0.0, 0.0, 0.0, 1.0);
     // context.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
 
     let attribute_position = context.get_attrib_location(program, "a_position");
     let buffer = context
         .create_buffer()
-        .ok_or_else(|| JsValue::from_str("fail to create buffer"))?;
+        .ok_or_else(|| Error::Context("failed to create a buffer".to_string()))?;
     context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
     unsafe {
         context.buffer_data_with_array_buffer_view(
@@ -383,13 +2310,13 @@ fn draw(context: &WebGl2RenderingContext, program: &WebGlProgram) -> Result<(),
 }
 
 pub fn compile_shader(
-    context: &WebGl2RenderingContext,
+    context: &GlContext,
     shader_type: u32,
     source: &str,
-) -> Result<WebGlShader, String> {
+) -> Result<WebGlShader, Error> {
     let shader = context
         .create_shader(shader_type)
-        .ok_or_else(|| String::from("unable to create shader object"))?;
+        .ok_or_else(|| Error::Context("failed to create a shader".to_string()))?;
     context.shader_source(&shader, source);
     context.compile_shader(&shader);
 
@@ -400,26 +2327,31 @@ pub fn compile_shader(
     {
         Ok(shader)
     } else {
-        Err(context
-            .get_shader_info_log(&shader)
-            .unwrap_or_else(|| String::from("unknown error creating shader")))
+        Err(Error::shader_compile(
+            source,
+            &context.get_shader_info_log(&shader).unwrap_or_default(),
+        ))
     }
 }
 
-pub fn link_program(context: &WebGl2RenderingContext) -> Result<WebGlProgram, String> {
+pub fn link_program(
+    context: &GlContext,
+    vertex_shader: &str,
+    fragment_shader: &str,
+) -> Result<WebGlProgram, Error> {
     let vert_shader = compile_shader(
-        &context,
+        context,
         WebGl2RenderingContext::VERTEX_SHADER,
-        VERTEX_SHADER,
+        vertex_shader,
     )?;
     let frag_shader = compile_shader(
-        &context,
+        context,
         WebGl2RenderingContext::FRAGMENT_SHADER,
-        FRAGMENT_SHADER,
+        fragment_shader,
     )?;
     let program = context
         .create_program()
-        .ok_or_else(|| String::from("unable to create shader object"))?;
+        .ok_or_else(|| Error::Context("failed to create a program".to_string()))?;
 
     context.attach_shader(&program, &vert_shader);
     context.attach_shader(&program, &frag_shader);
@@ -433,8 +2365,8 @@ pub fn link_program(context: &WebGl2RenderingContext) -> Result<WebGlProgram, St
         context.use_program(Some(&program));
         Ok(program)
     } else {
-        Err(context
-            .get_program_info_log(&program)
-            .unwrap_or_else(|| String::from("unknown error creating program object")))
+        Err(Error::Link(
+            context.get_program_info_log(&program).unwrap_or_default(),
+        ))
     }
 }
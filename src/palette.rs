@@ -0,0 +1,207 @@
+// Number of colors the palette is sampled into for the shader.
+pub const PALETTE_SIZE: usize = 256;
+
+// Iterations one pass through the palette takes at speed 1. With the default
+// palette this gives every iteration a color of its own, as the original
+// 16-color table did.
+pub const PALETTE_PERIOD: f32 = 16.;
+
+pub const DEFAULT_PALETTE: &str = "ultra_fractal";
+
+// A color at a position in [0, 1) along the palette.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorStop {
+    pub position: f32,
+    pub color: [u8; 3],
+}
+
+// A gradient through its color stops, sorted by position. It wraps around, so
+// the color after the last stop blends back into the first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub stops: Vec<ColorStop>,
+}
+
+impl Palette {
+    pub fn new(name: &str, stops: Vec<ColorStop>) -> Result<Palette, String> {
+        if stops.is_empty() {
+            return Err("a palette needs at least one color".to_string());
+        }
+        if let Some(stop) = stops
+            .iter()
+            .find(|stop| !(0. ..1.).contains(&stop.position))
+        {
+            return Err(format!(
+                "color stop positions must be in [0, 1), found {}",
+                stop.position
+            ));
+        }
+
+        let mut stops = stops;
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+        Ok(Palette {
+            name: name.to_string(),
+            stops,
+        })
+    }
+
+    // Spaces `colors` evenly, starting at position 0.
+    pub fn uniform(name: &str, colors: &[[u8; 3]]) -> Result<Palette, String> {
+        let stops = colors
+            .iter()
+            .enumerate()
+            .map(|(i, &color)| ColorStop {
+                position: i as f32 / colors.len() as f32,
+                color,
+            })
+            .collect();
+        Palette::new(name, stops)
+    }
+
+    pub fn builtin(name: &str) -> Option<Palette> {
+        let colors: &[[u8; 3]] = match name {
+            // Ultra Fractal's default gradient.
+            "ultra_fractal" => &[
+                [66, 30, 15],
+                [25, 7, 26],
+                [9, 1, 47],
+                [4, 4, 73],
+                [0, 7, 100],
+                [12, 44, 138],
+                [24, 82, 177],
+                [57, 125, 209],
+                [134, 181, 229],
+                [211, 236, 248],
+                [241, 233, 191],
+                [248, 201, 95],
+                [255, 170, 0],
+                [204, 128, 0],
+                [153, 87, 0],
+                [106, 52, 3],
+            ],
+            "fire" => &[
+                [0, 0, 0],
+                [128, 0, 0],
+                [230, 50, 0],
+                [255, 160, 0],
+                [255, 240, 120],
+                [255, 160, 0],
+                [230, 50, 0],
+                [128, 0, 0],
+            ],
+            "ocean" => &[
+                [0, 7, 30],
+                [0, 40, 90],
+                [0, 110, 160],
+                [90, 200, 220],
+                [230, 250, 255],
+                [90, 200, 220],
+                [0, 110, 160],
+                [0, 40, 90],
+            ],
+            "grayscale" => &[[0, 0, 0], [255, 255, 255]],
+            _ => return None,
+        };
+        Palette::uniform(name, colors).ok()
+    }
+
+    // Color at `position`, which wraps around every 1.
+    pub fn color(&self, position: f32) -> [u8; 3] {
+        let position = position.rem_euclid(1.);
+        let first = self.stops[0];
+        let last = self.stops[self.stops.len() - 1];
+        // Before the first stop and after the last one, blend into the stop of
+        // the neighboring period.
+        match self.stops.iter().position(|stop| stop.position > position) {
+            Some(0) => mix(
+                ColorStop {
+                    position: last.position - 1.,
+                    ..last
+                },
+                first,
+                position,
+            ),
+            Some(next) => mix(self.stops[next - 1], self.stops[next], position),
+            None => mix(
+                last,
+                ColorStop {
+                    position: first.position + 1.,
+                    ..first
+                },
+                position,
+            ),
+        }
+    }
+
+    // The palette sampled at `PALETTE_SIZE` evenly spaced positions, as RGBA.
+    pub fn texels(&self) -> Vec<u8> {
+        (0..PALETTE_SIZE)
+            .flat_map(|i| {
+                let [r, g, b] = self.color(i as f32 / PALETTE_SIZE as f32);
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::builtin(DEFAULT_PALETTE).unwrap()
+    }
+}
+
+fn mix(from: ColorStop, to: ColorStop, position: f32) -> [u8; 3] {
+    let width = to.position - from.position;
+    let t = if width > 0. {
+        (position - from.position) / width
+    } else {
+        0.
+    };
+    [0, 1, 2].map(|i| {
+        let (a, b) = (from.color[i] as f32, to.color[i] as f32);
+        (a + (b - a) * t).round() as u8
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtins_exist() {
+        for name in ["ultra_fractal", "fire", "ocean", "grayscale"] {
+            assert_eq!(Palette::builtin(name).unwrap().name, name);
+        }
+        assert_eq!(Palette::builtin("plaid"), None);
+    }
+
+    #[test]
+    fn rejects_palettes_without_colors() {
+        assert!(Palette::uniform("empty", &[]).is_err());
+        assert!(Palette::new("empty", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_stops_out_of_range() {
+        for position in [-0.1, 1., f32::NAN] {
+            let stops = vec![ColorStop {
+                position,
+                color: [0; 3],
+            }];
+            assert!(Palette::new("bad", stops).is_err(), "{position}");
+        }
+    }
+
+    #[test]
+    fn blends_between_stops_and_wraps_around() {
+        let palette = Palette::uniform("gray", &[[0; 3], [200; 3]]).unwrap();
+        assert_eq!(palette.color(0.), [0; 3]);
+        assert_eq!(palette.color(0.25), [100; 3]);
+        assert_eq!(palette.color(0.5), [200; 3]);
+        assert_eq!(palette.color(0.75), [100; 3]);
+        assert_eq!(palette.color(1.25), [100; 3]);
+        assert_eq!(palette.color(-0.25), [100; 3]);
+        assert_eq!(palette.texels().len(), PALETTE_SIZE * 4);
+    }
+}
@@ -0,0 +1,340 @@
+use dashu_float::FBig;
+
+use crate::{
+    error::Error,
+    viewport::{to_fbig, Viewport},
+    Fractal,
+};
+
+// Number of orbit points per row of the texture they are uploaded to. Must
+// match `Orbit()` in the fragment shader.
+pub const ORBIT_WIDTH: usize = 1024;
+
+// Orbit of the view center, computed with the full precision of the center.
+// Pixels only iterate their difference from it, which fits in a float.
+//
+// The Mandelbrot orbit starts at 0 rather than at the center, so that pixels
+// can be rebased onto its start like Julia pixels are rebased onto the center.
+pub fn reference_orbit(
+    viewport: &Viewport,
+    iterations: i32,
+    fractal: Fractal,
+    c: [f32; 2],
+) -> Result<Vec<[f64; 2]>, Error> {
+    let [re_center, im_center] = viewport.center.clone();
+    let (mut re, mut im, re_c, im_c) = match fractal {
+        Fractal::Mandelbrot => (FBig::ZERO, FBig::ZERO, re_center, im_center),
+        Fractal::Julia => (
+            re_center,
+            im_center,
+            to_fbig(c[0] as f64)?,
+            to_fbig(c[1] as f64)?,
+        ),
+    };
+
+    let mut orbit = Vec::with_capacity(iterations.max(0) as usize + 1);
+    for _ in 0..=iterations {
+        let z = [re.to_f64().value(), im.to_f64().value()];
+        orbit.push(z);
+        if z[0] * z[0] + z[1] * z[1] > 4. {
+            break;
+        }
+
+        let re2 = &re * &re;
+        let im2 = &im * &im;
+        let re_im = &re * &im;
+        re = re2 - im2 + &re_c;
+        im = &re_im + &re_im + &im_c;
+    }
+    Ok(orbit)
+}
+
+// Splits the half width of the view into `scale * 2^exponent` with `scale` in
+// [1, 2), since deep zooms are far below the smallest float.
+pub fn delta_scale(half_width: f64) -> (f32, i32) {
+    let exponent = half_width.log2().floor() as i32;
+    ((half_width / 2f64.powi(exponent)) as f32, exponent)
+}
+
+// Reference orbit packed as RG texels, `ORBIT_WIDTH` per row.
+pub fn orbit_texels(orbit: &[[f64; 2]]) -> (Vec<f32>, usize) {
+    let rows = orbit.len().div_ceil(ORBIT_WIDTH);
+    let mut texels = vec![0.; rows * ORBIT_WIDTH * 2];
+    for (texel, z) in texels.chunks_exact_mut(2).zip(orbit) {
+        texel[0] = z[0] as f32;
+        texel[1] = z[1] as f32;
+    }
+    (texels, rows)
+}
+
+// Relative error allowed for the series, about what a float can resolve.
+const SERIES_TOLERANCE: f64 = 1e-6;
+
+// Coefficients of the series `dz = a*d + b*d^2 + c*d^3` in the pixel offset
+// `d`, given in units of 2^delta_exponent. The series gives dz at iteration
+// `skip + 1` in units of 2^(delta_exponent + exponent), so pixels can start
+// there instead of at the first iteration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SeriesApproximation {
+    pub skip: i32,
+    pub coefficients: [[f32; 2]; 3],
+    pub exponent: i32,
+}
+
+impl SeriesApproximation {
+    // Starts at the first iteration, for when the series is no good.
+    pub const NONE: SeriesApproximation = SeriesApproximation {
+        skip: 0,
+        coefficients: [[1., 0.], [0., 0.], [0., 0.]],
+        exponent: 0,
+    };
+}
+
+// Advances the series along the reference orbit for as long as its error stays
+// below `SERIES_TOLERANCE`. The error is checked against the truncated cubic
+// term over the whole view, and against the corners of the view iterated
+// without the series.
+pub fn series_approximation(
+    orbit: &[[f64; 2]],
+    viewport: &Viewport,
+    iterations: i32,
+    fractal: Fractal,
+) -> SeriesApproximation {
+    let (delta_scale, delta_exponent) = delta_scale(viewport.half_width);
+    let unit = 2f64.powi(delta_exponent);
+    let (sin, cos) = viewport.rotation.sin_cos();
+    let probes = [[1., 1.], [-1., 1.], [-1., -1.], [1., -1.]].map(|[u, v]: [f64; 2]| {
+        let v = v / viewport.ratio;
+        [
+            (u * cos - v * sin) * delta_scale as f64,
+            (u * sin + v * cos) * delta_scale as f64,
+        ]
+    });
+    let radius = probes[0][0].hypot(probes[0][1]);
+    let (start, dc) = match fractal {
+        Fractal::Mandelbrot => (1, 1.),
+        Fractal::Julia => (0, 0.),
+    };
+
+    let mut a = [1., 0.];
+    let mut b = [0., 0.];
+    let mut c = [0., 0.];
+    let mut dz = probes;
+    let mut best = None;
+    let last = (orbit.len() as i32 - 2).min(iterations - 1 + start);
+    for m in start..last {
+        let z = orbit[m as usize];
+        let z2 = [2. * z[0], 2. * z[1]];
+        let a2 = mul(a, a);
+        let ab2 = mul([2. * a[0], 2. * a[1]], b);
+        a = add(mul(z2, a), [dc, 0.]);
+        b = add(mul(z2, b), scale(a2, unit));
+        c = add(mul(z2, c), scale(ab2, unit));
+        for (dz, d) in dz.iter_mut().zip(probes) {
+            *dz = add(add(mul(z2, *dz), scale(mul(*dz, *dz), unit)), scale(d, dc));
+        }
+
+        let truncation = norm(c) * radius.powi(3);
+        let valid = truncation <= SERIES_TOLERANCE * norm(a) * radius
+            && dz.iter().zip(probes).all(|(dz, d)| {
+                let series = add(add(mul(a, d), mul(b, mul(d, d))), mul(c, mul(d, mul(d, d))));
+                norm(sub(series, *dz)) <= SERIES_TOLERANCE * norm(*dz)
+            });
+        if !valid || !norm(a).is_finite() {
+            break;
+        }
+        best = Some((m + 1 - start, a, b, c));
+    }
+
+    let Some((skip, a, b, c)) = best else {
+        return SeriesApproximation::NONE;
+    };
+    let exponent = norm(a).max(norm(b)).max(norm(c)).log2().floor() as i32;
+    let shift = 2f64.powi(-exponent);
+    SeriesApproximation {
+        skip,
+        coefficients: [a, b, c].map(|[re, im]| [(re * shift) as f32, (im * shift) as f32]),
+        exponent,
+    }
+}
+
+fn add(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+fn sub(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn mul(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] * b[0] - a[1] * b[1], a[0] * b[1] + a[1] * b[0]]
+}
+
+fn scale(a: [f64; 2], s: f64) -> [f64; 2] {
+    [a[0] * s, a[1] * s]
+}
+
+fn norm(a: [f64; 2]) -> f64 {
+    a[0].hypot(a[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(re: f64, im: f64, half_width: f64) -> Viewport {
+        Viewport::new(re, im, half_width, 4., 3.).unwrap()
+    }
+
+    #[test]
+    fn orbits_start_where_their_fractal_does() {
+        let mandelbrot = viewport(-1., 0., 1e-12);
+        let orbit = reference_orbit(&mandelbrot, 10, Fractal::Mandelbrot, [0.5, 0.5]).unwrap();
+        // From 0, with the center as c; the center is in the period 2 bulb.
+        assert_eq!(orbit.len(), 11);
+        assert_eq!(orbit[..3], [[0., 0.], [-1., 0.], [0., 0.]]);
+
+        let julia = viewport(0.5, 0., 1e-12);
+        let orbit = reference_orbit(&julia, 10, Fractal::Julia, [0., 0.]).unwrap();
+        // From the center, with the Julia parameter as c.
+        assert_eq!(orbit.len(), 11);
+        assert_eq!(orbit[..3], [[0.5, 0.], [0.25, 0.], [0.0625, 0.]]);
+    }
+
+    #[test]
+    fn orbits_stop_at_the_first_escaped_point() {
+        let viewport = viewport(1., 0., 1e-12);
+        let orbit = reference_orbit(&viewport, 100, Fractal::Mandelbrot, [0., 0.]).unwrap();
+        // 2 lies on the escape radius, which is not outside it yet.
+        assert_eq!(orbit, [[0., 0.], [1., 0.], [2., 0.], [5., 0.]]);
+
+        let orbit = reference_orbit(&viewport, 0, Fractal::Mandelbrot, [0., 0.]).unwrap();
+        assert_eq!(orbit, [[0., 0.]]);
+    }
+
+    #[test]
+    fn delta_scale_splits_off_the_exponent() {
+        assert_eq!(delta_scale(1.), (1., 0));
+        assert_eq!(delta_scale(3.), (1.5, 1));
+        assert_eq!(delta_scale(0.75), (1.5, -1));
+        assert_eq!(delta_scale(2f64.powi(-1000)), (1., -1000));
+        let (scale, exponent) = delta_scale(1e-300);
+        assert!((1. ..2.).contains(&scale), "{scale}");
+        assert!((scale as f64 * 2f64.powi(exponent) / 1e-300 - 1.).abs() < 1e-7);
+    }
+
+    #[test]
+    fn orbit_texels_fill_rows_of_orbit_width() {
+        assert_eq!(orbit_texels(&[]), (vec![], 0));
+
+        let orbit: Vec<[f64; 2]> = (0..ORBIT_WIDTH).map(|i| [i as f64, -(i as f64)]).collect();
+        let (texels, rows) = orbit_texels(&orbit);
+        assert_eq!((texels.len(), rows), (ORBIT_WIDTH * 2, 1));
+        assert_eq!(texels[2 * 1023..], [1023., -1023.]);
+
+        // The last row is only partly used, and the rest of it left at zero.
+        let orbit: Vec<[f64; 2]> = (0..ORBIT_WIDTH + 3).map(|i| [i as f64, 0.5]).collect();
+        let (texels, rows) = orbit_texels(&orbit);
+        assert_eq!((texels.len(), rows), (ORBIT_WIDTH * 4, 2));
+        assert_eq!(
+            texels[2 * ORBIT_WIDTH..][..6],
+            [1024., 0.5, 1025., 0.5, 1026., 0.5]
+        );
+        assert!(texels[2 * (ORBIT_WIDTH + 3)..].iter().all(|&x| x == 0.));
+    }
+
+    // Iterates the offset `d` of a pixel from the reference orbit directly,
+    // up to orbit index `end`, in units of 2^delta_exponent.
+    fn iterate_delta(
+        orbit: &[[f64; 2]],
+        d: [f64; 2],
+        end: i32,
+        unit: f64,
+        fractal: Fractal,
+    ) -> [f64; 2] {
+        let (start, dc) = match fractal {
+            Fractal::Mandelbrot => (1, 1.),
+            Fractal::Julia => (0, 0.),
+        };
+        let mut dz = d;
+        for m in start..end {
+            let z = orbit[m as usize];
+            dz = add(
+                add(mul([2. * z[0], 2. * z[1]], dz), scale(mul(dz, dz), unit)),
+                scale(d, dc),
+            );
+        }
+        dz
+    }
+
+    // The series at `d`, in units of 2^delta_exponent.
+    fn evaluate(series: &SeriesApproximation, d: [f64; 2]) -> [f64; 2] {
+        let [a, b, c] = series.coefficients.map(|[re, im]| [re as f64, im as f64]);
+        let value = add(add(mul(a, d), mul(b, mul(d, d))), mul(c, mul(d, mul(d, d))));
+        scale(value, 2f64.powi(series.exponent))
+    }
+
+    // Points of the view, in units of 2^delta_exponent: its corners, where the
+    // series is checked, and some inside it.
+    fn offsets(viewport: &Viewport) -> Vec<[f64; 2]> {
+        let (delta_scale, _) = delta_scale(viewport.half_width);
+        let h = 1. / viewport.ratio;
+        [
+            [1., h],
+            [-1., h],
+            [-1., -h],
+            [1., -h],
+            [0.5, 0.1],
+            [-0.3, -0.6],
+            [1e-3, 0.],
+        ]
+        .map(|d| scale(d, delta_scale as f64))
+        .into()
+    }
+
+    #[test]
+    fn series_matches_iterated_deltas() {
+        for (fractal, c) in [
+            (Fractal::Mandelbrot, [0., 0.]),
+            (Fractal::Julia, [-0.8, 0.156]),
+        ] {
+            let viewport = viewport(-0.75, 0.1, 1e-12);
+            let (_, delta_exponent) = delta_scale(viewport.half_width);
+            let unit = 2f64.powi(delta_exponent);
+            let start = (fractal == Fractal::Mandelbrot) as i32;
+            let mut skips = Vec::new();
+            for iterations in [3, 6, 12, 100] {
+                let orbit = reference_orbit(&viewport, iterations, fractal, c).unwrap();
+                let series = series_approximation(&orbit, &viewport, iterations, fractal);
+                assert!(series.skip > 0, "{series:?}");
+                skips.push(series.skip);
+
+                for d in offsets(&viewport) {
+                    let expected = iterate_delta(&orbit, d, start + series.skip, unit, fractal);
+                    let error = norm(sub(evaluate(&series, d), expected));
+                    // The bound the series is kept within, and the rounding of
+                    // its coefficients to floats.
+                    assert!(
+                        error <= (SERIES_TOLERANCE + 1e-6) * norm(expected),
+                        "{fractal:?} skipping {}: {error:e} at {d:?}",
+                        series.skip
+                    );
+                }
+            }
+            // Limited by the iterations first, and then by the error bound.
+            assert!(skips.windows(2).all(|w| w[0] < w[1]), "{skips:?}");
+            assert!(skips[3] < 99, "{skips:?}");
+        }
+    }
+
+    #[test]
+    fn series_falls_back_when_the_orbit_escapes() {
+        let viewport = viewport(1., 1., 1e-12);
+        let orbit = reference_orbit(&viewport, 1000, Fractal::Mandelbrot, [0., 0.]).unwrap();
+        assert!(orbit.len() < 4);
+        assert_eq!(
+            series_approximation(&orbit, &viewport, 1000, Fractal::Mandelbrot),
+            SeriesApproximation::NONE
+        );
+    }
+}
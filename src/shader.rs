@@ -0,0 +1,1102 @@
+use crate::{
+    palette::{PALETTE_PERIOD, PALETTE_SIZE},
+    Coloring, Formula, Precision, MAX_ITERATIONS, MAX_SAMPLES,
+};
+
+// The fragment shader is assembled from pieces, so that each program only
+// holds the formula, coloring and precision it draws with. Pieces leave
+// `$marker` lines where other pieces go in, filled in by `fill()`. Everything
+// else, such as the fractal, the interior and the trap shape, is a uniform
+// and needs no program of its own.
+
+// The WebGL version a program is built for: GLSL ES 3.00 for WebGL 2, and
+// GLSL ES 1.00 for WebGL 1, which cannot hold the orbit of the perturbation
+// backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GlVersion {
+    WebGl1,
+    WebGl2,
+}
+
+// What a fragment shader is built for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderConfig {
+    pub version: GlVersion,
+    pub formula: Formula,
+    // The GLSL of the custom formula, a complex expression in `z` and `c`.
+    // Empty unless `formula` is `Formula::Custom`.
+    pub custom: String,
+    // Only `Formula::Quadratic` has the double precision and perturbation
+    // backends, which iterate z^2 + c whatever the formula.
+    pub precision: Precision,
+    pub coloring: Coloring,
+}
+
+pub fn vertex_shader(version: GlVersion) -> &'static str {
+    match version {
+        GlVersion::WebGl1 => VERTEX_SHADER_100,
+        GlVersion::WebGl2 => VERTEX_SHADER_300,
+    }
+}
+
+static VERTEX_SHADER_300: &str = r#"#version 300 es
+    in vec2 a_position;
+
+    void main() {
+        gl_Position = vec4(a_position, 0.0, 1.0);
+    }
+"#;
+
+static VERTEX_SHADER_100: &str = r#"
+    attribute vec2 a_position;
+
+    void main() {
+        gl_Position = vec4(a_position, 0.0, 1.0);
+    }
+"#;
+
+// Draws the lower left `extent` of `image`, as a fraction of its size, over
+// the whole target, which `blit_framebuffer` does in WebGL 2 only. GLSL ES 1.00,
+// so it links with `vertex_shader(GlVersion::WebGl1)` in either version.
+pub const COPY_SHADER: &str = r#"
+    precision highp float;
+
+    uniform sampler2D	image;
+    uniform vec2	extent;
+    uniform vec2	resolution;
+
+    void main() {
+        gl_FragColor = texture2D(image, gl_FragCoord.xy / resolution * extent);
+    }
+"#;
+
+// Paints the values a fragment shader draws when `values` is set, so that the
+// palette can cycle without drawing the view again. Built for GLSL ES 1.00,
+// which WebGL 2 runs too, like `COPY_SHADER`.
+pub fn paint_shader() -> String {
+    let constants = format!("const float PALETTE_SIZE = {:?};", PALETTE_SIZE as f32);
+    [
+        &fill(PAINT_HEADER, &[("$constants", &constants)]),
+        PAINT,
+        PAINT_MAIN,
+    ]
+    .concat()
+}
+
+pub fn fragment_shader(config: &ShaderConfig) -> String {
+    let tracking = tracking(config.coloring, config.precision);
+    let backend = match config.precision {
+        Precision::Single => SINGLE,
+        Precision::Double => DOUBLE,
+        Precision::Perturbation => PERTURBATION,
+    };
+    let mut pieces = vec![("$start", tracking.start), ("$escaped", tracking.escaped)];
+    let track: Vec<_> = ["z", "Z", "z.xz"]
+        .map(|z| (format!("$track({z})"), tracking.track.replace("$z", z)))
+        .into();
+    pieces.extend(
+        track
+            .iter()
+            .map(|(marker, piece)| (&marker[..], &piece[..])),
+    );
+
+    let (trap, color) = match config.coloring {
+        Coloring::Smooth => ("", ""),
+        Coloring::Distance => ("", DISTANCE_COLOR),
+        Coloring::Trap => (TRAP, TRAP_COLOR),
+    };
+    let (interior_shade, shade) = match config.formula {
+        Formula::Quadratic => (INTERIOR_SHADE, SHADE),
+        _ => ("", ""),
+    };
+
+    // Loops run to constant bounds and break out early, as GLSL ES 1.00
+    // requires. The palette is laid out as the CPU renderer samples it.
+    let constants = [
+        format!("const int MAX_ITERATIONS = {MAX_ITERATIONS};"),
+        format!("const int MAX_SAMPLES = {MAX_SAMPLES};"),
+        format!("const float PALETTE_SIZE = {:?};", PALETTE_SIZE as f32),
+        format!("const float PALETTE_PERIOD = {PALETTE_PERIOD:?};"),
+    ]
+    .join("\n");
+    let (version, output, jitter) = match config.version {
+        GlVersion::WebGl1 => ("", COMPATIBILITY_100, JITTER_100),
+        GlVersion::WebGl2 => ("#version 300 es", "out vec4 fragmentColor;", JITTER_300),
+    };
+    let header = fill(
+        HEADER,
+        &[
+            ("$version", version),
+            ("$constants", &constants),
+            ("$output", output),
+        ],
+    );
+
+    [
+        &header,
+        COMPLEX,
+        &formula(config),
+        BAILOUT,
+        tracking.globals,
+        trap,
+        ROTATE,
+        &fill(backend, &pieces),
+        PALETTE,
+        PAINT,
+        PERIOD,
+        interior_shade,
+        &fill(INTERIOR, &[("$shade", shade)]),
+        &fill(SAMPLE, &[("$color", color)]),
+        jitter,
+        MAIN,
+    ]
+    .concat()
+}
+
+// Step and StepDerivative for the formula.
+fn formula(config: &ShaderConfig) -> String {
+    let custom;
+    let step = match config.formula {
+        Formula::Quadratic => "return cmul(z, z) + c;",
+        Formula::Multibrot => "return Power(z, power) + c;",
+        Formula::BurningShip => "return vec2(z.x * z.x - z.y * z.y, abs(2. * z.x * z.y)) + c;",
+        Formula::Tricorn => "return vec2(z.x * z.x - z.y * z.y, -2. * z.x * z.y) + c;",
+        Formula::Celtic => "return vec2(abs(z.x * z.x - z.y * z.y), 2. * z.x * z.y) + c;",
+        Formula::Buffalo => "return abs(cmul(z, z)) + c;",
+        Formula::Custom => {
+            custom = format!("return {};", config.custom);
+            &custom
+        }
+    };
+    let derivative = match config.formula {
+        Formula::Quadratic => "return 2. * cmul(z, dz);",
+        Formula::Multibrot => "return power * cmul(Power(z, power - 1.), dz);",
+        // The absolute values and conjugates only reflect it, so its length is
+        // still right.
+        Formula::BurningShip | Formula::Tricorn | Formula::Celtic | Formula::Buffalo => {
+            "return 2. * cmul(z, dz);"
+        }
+        Formula::Custom => CUSTOM_DERIVATIVE,
+    };
+    fill(FORMULA, &[("$step", step), ("$derivative", derivative)])
+}
+
+// Replaces each line of `template` that is only a marker by the lines of its
+// piece, indented as the marker was, and leaves it out when the piece is
+// empty.
+fn fill(template: &str, pieces: &[(&str, &str)]) -> String {
+    let mut source = String::new();
+    for line in template.lines() {
+        let trimmed = line.trim_start();
+        match pieces.iter().find(|(marker, _)| *marker == trimmed) {
+            Some((_, piece)) => {
+                let indent = &line[..line.len() - trimmed.len()];
+                for piece_line in piece.lines() {
+                    source.push_str(indent);
+                    source.push_str(piece_line);
+                    source.push('\n');
+                }
+            }
+            None => {
+                source.push_str(line);
+                source.push('\n');
+            }
+        }
+    }
+    source
+}
+
+// What a coloring keeps track of along the orbit: its globals, and the lines
+// the escape loop of a backend runs before it, at every iteration with the
+// orbit's point as `$z`, and once the point escapes.
+struct Tracking {
+    globals: &'static str,
+    start: &'static str,
+    track: &'static str,
+    escaped: &'static str,
+}
+
+fn tracking(coloring: Coloring, precision: Precision) -> Tracking {
+    match (coloring, precision) {
+        (Coloring::Smooth, _) => Tracking {
+            globals: "",
+            start: "",
+            track: "",
+            escaped: "",
+        },
+        // The derivative dz of z with respect to the pixel's point is iterated
+        // too, starting from 1, with dc added to it every iteration: 1 for the
+        // Mandelbrot set and 0 for Julia sets.
+        (Coloring::Distance, Precision::Single) => Tracking {
+            globals: DISTANCE,
+            start: "vec2 dz = vec2(1., 0.);",
+            track: "dz = StepDerivative($z, c, dz) + vec2(dc, 0.);",
+            escaped: "log_derivative = log2(length(dz));",
+        },
+        // The derivative needs no more than a float.
+        (Coloring::Distance, Precision::Double) => Tracking {
+            globals: DISTANCE,
+            start: "vec2 dz = vec2(1., 0.);",
+            track: "dz = 2. * cmul($z, dz) + vec2(dc, 0.);",
+            escaped: "log_derivative = log2(length(dz));",
+        },
+        (Coloring::Distance, Precision::Perturbation) => Tracking {
+            globals: DISTANCE,
+            start: "",
+            track: "Derive($z, mandelbrot, derivative, derivative_exponent);",
+            escaped: "log_derivative = log2(length(derivative)) + float(derivative_exponent);",
+        },
+        (Coloring::Trap, _) => Tracking {
+            globals: "",
+            start: "trap = 1e20;",
+            track: "trap = min(trap, TrapDistance($z));",
+            escaped: "",
+        },
+    }
+}
+
+static HEADER: &str = r#"$version
+    precision highp float;
+    precision highp int;
+
+    $constants
+
+    uniform vec2	center;
+    uniform vec2	center_lo;
+    uniform vec2	scale;
+    uniform vec2	scale_lo;
+    uniform float	rotation;
+
+    uniform highp sampler2D	orbit;
+    uniform int		orbit_length;
+    uniform float	delta_scale;
+    uniform int		delta_exponent;
+
+    uniform int		series_skip;
+    uniform vec2	series[3];
+    uniform int		series_exponent;
+
+    uniform vec2	resolution;
+    uniform int		iterations;
+
+    uniform int		fractal;
+    uniform vec2	c;
+    uniform float	power;
+
+    uniform int		samples;
+    uniform int		jitter;
+    uniform int		values;
+
+    uniform int		interior;
+
+    uniform int		trap_shape;
+    uniform vec2	trap_center;
+    uniform vec2	trap_direction;
+    uniform float	trap_radius;
+    uniform float	trap_width;
+
+    uniform sampler2D	palette;
+    uniform float	palette_speed;
+    uniform float	palette_offset;
+
+    $output
+"#;
+
+// What GLSL ES 1.00 lacks or names otherwise.
+static COMPATIBILITY_100: &str = r#"#define texture texture2D
+#define fragmentColor gl_FragColor
+
+float sinh(float x) {
+    return (exp(x) - exp(-x)) / 2.;
+}
+
+float cosh(float x) {
+    return (exp(x) + exp(-x)) / 2.;
+}"#;
+
+static COMPLEX: &str = r#"
+    vec2 cmul(vec2 a, vec2 b) {
+        return vec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+    }
+
+    vec2 cdiv(vec2 a, vec2 b) {
+        return vec2(dot(a, b), a.y * b.x - a.x * b.y) / dot(b, b);
+    }
+
+    vec2 cexp(vec2 a) {
+        return exp(a.x) * vec2(cos(a.y), sin(a.y));
+    }
+
+    vec2 clog(vec2 a) {
+        return vec2(log(length(a)), atan(a.y, a.x));
+    }
+
+    vec2 csin(vec2 a) {
+        return vec2(sin(a.x) * cosh(a.y), cos(a.x) * sinh(a.y));
+    }
+
+    vec2 ccos(vec2 a) {
+        return vec2(cos(a.x) * cosh(a.y), -sin(a.x) * sinh(a.y));
+    }
+
+    vec2 cpow(vec2 a, vec2 b) {
+        return a == vec2(0.) ? a : cexp(cmul(b, clog(a)));
+    }
+
+    vec2 conj(vec2 a) {
+        return vec2(a.x, -a.y);
+    }
+
+    // z^n, by repeated multiplication when n is a small positive integer.
+    vec2 Power(vec2 z, float n) {
+        if (fract(n) == 0. && n >= 1. && n <= 64.) {
+            vec2 w = z;
+            for (int k = 1; k < 64; ++k) {
+                if (k >= int(n)) break;
+                w = cmul(w, z);
+            }
+            return w;
+        }
+        if (z == vec2(0.)) return z;
+        float angle = atan(z.y, z.x) * n;
+        return pow(length(z), n) * vec2(cos(angle), sin(angle));
+    }
+"#;
+
+static FORMULA: &str = r#"
+    // One iteration of the formula.
+    vec2 Step(vec2 z, vec2 c) {
+        $step
+    }
+
+    // Derivative of Step with respect to z, times dz.
+    vec2 StepDerivative(vec2 z, vec2 c, vec2 dz) {
+        $derivative
+    }
+"#;
+
+// A central difference along dz, as a custom formula could be anything.
+static CUSTOM_DERIVATIVE: &str = r#"float length_dz = length(dz);
+if (length_dz == 0.) return dz;
+vec2 h = dz / length_dz * 1e-3;
+return (Step(z + h, c) - Step(z - h, c)) / 2e-3 * length_dz;"#;
+
+static BAILOUT: &str = r#"
+    bool Escaped(vec2 z) {
+        return dot(z, z) > 4.0;
+    }
+"#;
+
+static DISTANCE: &str = r#"
+    // Log2 of the length of the derivative of z once it escapes.
+    float log_derivative;
+"#;
+
+static TRAP: &str = r#"
+    // The distance the orbit came closest to the trap by before escaping.
+    float trap;
+
+    // Distance from z to the orbit trap: a point, a line along
+    // trap_direction, a circle, or a cross of two lines, which Pickover stalks
+    // are too.
+    float TrapDistance(vec2 z) {
+        vec2 d = z - trap_center;
+        float along = abs(dot(d, trap_direction));
+        float across = abs(dot(d, vec2(-trap_direction.y, trap_direction.x)));
+        if (trap_shape == 0) return length(d);
+        if (trap_shape == 1) return across;
+        if (trap_shape == 2) return abs(length(d) - trap_radius);
+        return min(along, across);
+    }
+"#;
+
+// Each backend ends with `Iterate()`, which escapes the pixel at `uv`, from
+// -1 to 1 across the view, and sets these.
+static ROTATE: &str = r#"
+    // The pixel's point, as near as a float gets to it.
+    vec2 point;
+    // Log2 of the distance between pixels, in the units the derivative is
+    // taken in.
+    float log_pixel;
+
+    vec2 Rotate(vec2 v) {
+        return vec2(
+            v.x * cos(rotation) - v.y * sin(rotation),
+            v.x * sin(rotation) + v.y * cos(rotation)
+        );
+    }
+"#;
+
+static SINGLE: &str = r#"
+    // dc is 1 for the Mandelbrot set and 0 for Julia sets.
+    vec3 Escape(vec2 z, vec2 c, float dc) {
+        $start
+        for (int i = 1; i <= MAX_ITERATIONS; ++i) {
+            if (i > iterations) break;
+            if (Escaped(z)) {
+                $escaped
+                return vec3(z, float(i));
+            }
+
+            $track(z)
+            z = Step(z, c);
+        }
+        return vec3(z, 0.);
+    }
+
+    vec3 Iterate(vec2 uv) {
+        point = center + Rotate(uv * scale);
+        log_pixel = log2(2. * scale.x / resolution.x);
+        return fractal == 1 ? Escape(point, c, 0.) : Escape(point, point, 1.);
+    }
+"#;
+
+static DOUBLE: &str = r#"
+    // Double-float arithmetic: a vec2 holds the unevaluated sum hi + lo.
+    // https://andrewthall.org/papers/df64_qf128.pdf
+    vec2 df_quick_two_sum(float a, float b) {
+        float s = a + b;
+        return vec2(s, b - (s - a));
+    }
+
+    vec2 df_two_sum(float a, float b) {
+        float s = a + b;
+        float v = s - a;
+        return vec2(s, (a - (s - v)) + (b - v));
+    }
+
+    vec2 df_add(vec2 a, vec2 b) {
+        vec2 s = df_two_sum(a.x, b.x);
+        s.y += a.y + b.y;
+        return df_quick_two_sum(s.x, s.y);
+    }
+
+    vec2 df_split(float a) {
+        float t = a * 4097.;
+        float hi = t - (t - a);
+        return vec2(hi, a - hi);
+    }
+
+    vec2 df_two_prod(float a, float b) {
+        float p = a * b;
+        vec2 sa = df_split(a);
+        vec2 sb = df_split(b);
+        float err = ((sa.x * sb.x - p) + sa.x * sb.y + sa.y * sb.x) + sa.y * sb.y;
+        return vec2(p, err);
+    }
+
+    vec2 df_mul(vec2 a, vec2 b) {
+        vec2 p = df_two_prod(a.x, b.x);
+        p.y += a.x * b.y + a.y * b.x;
+        return df_quick_two_sum(p.x, p.y);
+    }
+
+    // Iterates z^2 + c with complex numbers stored as
+    // (re.hi, re.lo, im.hi, im.lo). dc is 1 for the Mandelbrot set and 0 for
+    // Julia sets.
+    vec3 DeepEscape(vec4 z, vec4 c, float dc) {
+        $start
+        for (int i = 1; i <= MAX_ITERATIONS; ++i) {
+            if (i > iterations) break;
+            if (Escaped(z.xz)) {
+                $escaped
+                return vec3(z.x, z.z, float(i));
+            }
+
+            $track(z.xz)
+            vec2 re2 = df_mul(z.xy, z.xy);
+            vec2 im2 = df_mul(z.zw, z.zw);
+            vec2 re_im = df_mul(z.xy, z.zw);
+            z = vec4(
+                df_add(df_add(re2, -im2), c.xy),
+                df_add(df_add(re_im, re_im), c.zw)
+            );
+        }
+        return vec3(z.x, z.z, 0.);
+    }
+
+    vec3 Iterate(vec2 uv) {
+        vec2 d = Rotate(uv * vec2(1., scale.y / scale.x));
+        vec2 half_width = vec2(scale.x, scale_lo.x);
+        vec4 p = vec4(
+            df_add(vec2(center.x, center_lo.x), df_mul(vec2(d.x, 0.), half_width)),
+            df_add(vec2(center.y, center_lo.y), df_mul(vec2(d.y, 0.), half_width))
+        );
+        point = p.xz;
+        log_pixel = log2(2. * scale.x / resolution.x);
+        return fractal == 1 ?
+            DeepEscape(p, vec4(c.x, 0., c.y, 0.), 0.) :
+            DeepEscape(p, p, 1.);
+    }
+"#;
+
+static PERTURBATION: &str = r#"
+    vec2 Orbit(int m) {
+        return texelFetch(orbit, ivec2(m % 1024, m / 1024), 0).xy;
+    }
+
+    // One iteration of the derivative in PerturbedEscape.
+    void Derive(vec2 z, float mandelbrot, inout vec2 derivative, inout int derivative_exponent) {
+        derivative = 2.0 * cmul(z, derivative)
+            + vec2(mandelbrot * exp2(float(delta_exponent - derivative_exponent)), 0.);
+        if (max(abs(derivative.x), abs(derivative.y)) > 4294967296.) {
+            derivative /= 4294967296.;
+            derivative_exponent += 32;
+        }
+    }
+
+    // Iterates the difference dz of a pixel from the reference orbit of
+    // z^2 + c, from iteration i at its point m. While dz is too small for a
+    // float it is kept in units of 2^exponent, and dc always is in units of
+    // 2^delta_exponent.
+    //
+    // The derivative of z with respect to the pixel's offset d is too small
+    // for a float at first, so it is kept in units of 2^derivative_exponent,
+    // and has 2^delta_exponent added every iteration when `mandelbrot` is 1.
+    //
+    // The iterations skipped by the series approximation are left out of the
+    // orbit trap.
+    vec3 PerturbedEscape(
+        int i,
+        int m,
+        vec2 dz,
+        int exponent,
+        vec2 dc,
+        vec2 derivative,
+        int derivative_exponent,
+        float mandelbrot
+    ) {
+        $start
+        if (exponent >= -64) {
+            dz *= exp2(float(exponent));
+            dc *= exp2(float(delta_exponent));
+        }
+        for(; i <= iterations ; ++i) {
+            vec2 Z = Orbit(m);
+            if (exponent < -64) {
+                // dz is negligible next to Z here.
+                if (Escaped(Z)) {
+                    $escaped
+                    return vec3(Z, float(i));
+                }
+
+                $track(Z)
+                dz = 2.0 * cmul(Z, dz) + exp2(float(exponent)) * cmul(dz, dz)
+                    + dc * exp2(float(delta_exponent - exponent));
+                ++m;
+                if (max(abs(dz.x), abs(dz.y)) > 4294967296.) {
+                    dz /= 4294967296.;
+                    exponent += 32;
+                    if (exponent >= -64) {
+                        dz *= exp2(float(exponent));
+                        dc *= exp2(float(delta_exponent));
+                    }
+                }
+                continue;
+            }
+
+            vec2 z = Z + dz;
+            if (Escaped(z)) {
+                $escaped
+                return vec3(z, float(i));
+            }
+
+            $track(z)
+            // Rebase onto the start of the reference orbit when the pixel is
+            // closer to it than to the current reference point, or when the
+            // reference has run out. This keeps dz small and avoids glitches.
+            vec2 rebased = z - Orbit(0);
+            if (dot(rebased, rebased) < dot(dz, dz) || m == orbit_length - 1) {
+                dz = rebased;
+                m = 0;
+                Z = Orbit(0);
+            }
+
+            dz = 2.0 * cmul(Z, dz) + cmul(dz, dz) + dc;
+            ++m;
+        }
+        vec2 z = exponent < -64 ? Orbit(m) : Orbit(m) + dz;
+        return vec3(z, 0.);
+    }
+
+    vec3 Iterate(vec2 uv) {
+        // Offset from the view center in units of 2^delta_exponent.
+        vec2 d = Rotate(uv * vec2(1., resolution.y / resolution.x)) * delta_scale;
+        point = center + d * exp2(float(delta_exponent));
+        log_pixel = log2(2. * delta_scale / resolution.x);
+        // The Mandelbrot orbit starts at 0, one point before the pixel.
+        int start = fractal == 1 ? 0 : 1;
+        vec2 dc = fractal == 1 ? vec2(0.) : d;
+        // Skip the iterations covered by the series approximation.
+        vec2 d2 = cmul(d, d);
+        vec2 dz = cmul(series[0], d) + cmul(series[1], d2) + cmul(series[2], cmul(d2, d));
+        // The derivative of the series with respect to d.
+        vec2 derivative = series[0] + 2. * cmul(series[1], d) + 3. * cmul(series[2], d2);
+        return PerturbedEscape(
+            1 + series_skip,
+            start + series_skip,
+            dz,
+            delta_exponent + series_exponent,
+            dc,
+            derivative,
+            delta_exponent + series_exponent,
+            fractal == 1 ? 0. : 1.
+        );
+    }
+"#;
+
+static PALETTE: &str = r#"
+    // Value of the point `position` passes through the palette, before speed
+    // and offset, at full shade. The offset is left to Paint(), and whole
+    // passes dropped, so that values can be kept while the palette cycles.
+    vec4 PaletteValue(float position) {
+        float p = fract(position * palette_speed);
+        return vec4(p, p, 0., 1.);
+    }
+
+    // https://en.wikipedia.org/wiki/Plotting_algorithms_for_the_Mandelbrot_set
+    vec4 SmoothValue(int i, vec2 z) {
+        float log_zn = log(z.x * z.x + z.y * z.y) / 2.;
+        float nu = log2(log_zn / log(2.)) / log2(power);
+        float it = float(i) + 1. - nu;
+
+        return PaletteValue(it / PALETTE_PERIOD);
+    }
+"#;
+
+// Colors the values points are drawn as: two positions in the palette, how
+// far to blend from the first to the second, and a shade the blend is
+// darkened by. Also used by the paint shader, where `texture` may be a macro.
+static PAINT: &str = r#"
+    vec4 Paint(vec4 value) {
+        // Offset by half a texel so that filtering blends neighboring colors
+        // the way the palette does between its stops.
+        vec2 position = fract(palette_offset + value.xy) + 0.5 / PALETTE_SIZE;
+        vec3 color = mix(
+            texture(palette, vec2(position.x, 0.5)).rgb,
+            texture(palette, vec2(position.y, 0.5)).rgb,
+            value.z
+        );
+        return vec4(color * value.w, 1.);
+    }
+"#;
+
+// Distances inside the set are only estimated for z^2 + c.
+static INTERIOR_SHADE: &str = r#"
+    // Shade of a point c inside the set by its distance to the boundary, in
+    // [0, 1] from the boundary to a few pixels in, estimated from the
+    // attracting cycle of the given period through z.
+    // https://en.wikipedia.org/wiki/Plotting_algorithms_for_the_Mandelbrot_set#Interior_distance_estimation
+    //
+    // Julia sets only have the derivative with respect to z, so they are
+    // shaded by 1 - |dz|^2 instead, which goes to 0 as the cycle stops
+    // attracting near the boundary.
+    float InteriorShade(vec2 z, vec2 c, int period, float pixel) {
+        vec2 dz = vec2(1., 0.);
+        vec2 dc = vec2(0.);
+        vec2 dzdz = vec2(0.);
+        vec2 dcdz = vec2(0.);
+        for (int p = 0; p < MAX_PERIOD; ++p) {
+            if (p >= period) break;
+            dcdz = 2. * (cmul(z, dcdz) + cmul(dz, dc));
+            dzdz = 2. * (cmul(z, dzdz) + cmul(dz, dz));
+            dz = 2. * cmul(z, dz);
+            dc = 2. * cmul(z, dc) + vec2(1., 0.);
+            z = cmul(z, z) + c;
+        }
+        float attraction = 1. - dot(dz, dz);
+        if (fractal == 1) return attraction;
+
+        // dc / (1 - dz)
+        vec2 w = vec2(1., 0.) - dz;
+        vec2 ratio = vec2(dc.x * w.x + dc.y * w.y, dc.y * w.x - dc.x * w.y) / dot(w, w);
+        float distance = attraction / length(dcdz + cmul(dzdz, ratio));
+        return distance / pixel / 4.;
+    }
+"#;
+
+static SHADE: &str = r#"if (interior == 3) {
+    value.w = sqrt(clamp(InteriorShade(z, c, period, pixel), 0., 1.));
+}"#;
+
+static PAINT_HEADER: &str = r#"
+    precision highp float;
+
+    #define texture texture2D
+
+    $constants
+
+    uniform highp sampler2D	values;
+    uniform vec2	resolution;
+
+    uniform sampler2D	palette;
+    uniform float	palette_offset;
+"#;
+
+static PAINT_MAIN: &str = r#"
+    void main() {
+        gl_FragColor = Paint(texture(values, gl_FragCoord.xy / resolution));
+    }
+"#;
+
+static PERIOD: &str = r#"
+    // Longest cycle looked for inside the set.
+    const int MAX_PERIOD = 64;
+
+    // Period of the cycle the orbit of a point inside the set has been drawn
+    // into, with z the last point of that orbit, or 0 if none is found.
+    int Period(vec2 z, vec2 c) {
+        vec2 start = z;
+        for (int p = 1; p <= MAX_PERIOD; ++p) {
+            z = Step(z, c);
+            if (length(z - start) < 1e-4) return p;
+        }
+        return 0;
+    }
+"#;
+
+static INTERIOR: &str = r#"
+    // Value of a point c inside the set, whose orbit ended at z.
+    vec4 InteriorValue(vec2 z, vec2 c, float pixel) {
+        if (interior == 1) return PaletteValue(length(z) / 2.);
+
+        // Black, as no shade at all.
+        int period = interior >= 2 ? Period(z, c) : 0;
+        if (period == 0) return vec4(0.);
+
+        // Each period gets a color of its own, as iterations do outside.
+        vec4 value = PaletteValue(float(period) / PALETTE_PERIOD);
+        $shade
+        return value;
+    }
+"#;
+
+// Darkens the color within a few pixels of the boundary.
+// https://en.wikipedia.org/wiki/Plotting_algorithms_for_the_Mandelbrot_set#Distance_estimates
+static DISTANCE_COLOR: &str = r#"float r = length(z);
+float distance = exp2(log2(r * log(r)) - log_derivative - log_pixel);
+value.w = sqrt(clamp(distance / 4., 0., 1.));"#;
+
+// The palette spans trap_width from the trap. Pickover stalks only show over
+// the smooth coloring where the orbit came that close.
+static TRAP_COLOR: &str = r#"float t = trap / trap_width;
+vec4 trapped = PaletteValue(t);
+value = trap_shape == 4 ? vec4(trapped.x, value.x, clamp(t, 0., 1.), 1.) : trapped;"#;
+
+static SAMPLE: &str = r#"
+    // Value of the point `pixel`, given in the coordinates of gl_FragCoord,
+    // as painted by Paint().
+    vec4 Sample(vec2 pixel) {
+        vec3 m = Iterate(pixel / resolution * 2. - 1.);
+        vec2 z = vec2(m.x, m.y);
+        int i = int(m.z);
+        if (i == 0) {
+            return InteriorValue(z, fractal == 1 ? c : point, 2. * scale.x / resolution.x);
+        }
+
+        vec4 value = SmoothValue(i, z);
+        $color
+        return value;
+    }
+"#;
+
+static JITTER_300: &str = r#"
+    // https://nullprogram.com/blog/2018/07/31/
+    uint Hash(uint x) {
+        x ^= x >> 16;
+        x *= 0x7feb352du;
+        x ^= x >> 15;
+        x *= 0x846ca68bu;
+        x ^= x >> 16;
+        return x;
+    }
+
+    // A random point of the cell of the sample with the given index, each
+    // coordinate in [0, 1).
+    vec2 Jitter(vec2 pixel, int index) {
+        uvec2 p = uvec2(pixel);
+        uint h = Hash(p.x + Hash(p.y + Hash(uint(index))));
+        return vec2(h & 0xffffu, h >> 16) / 65536.;
+    }
+"#;
+
+// GLSL ES 1.00 has no unsigned integers, so this jitter is not the CPU
+// renderer's.
+static JITTER_100: &str = r#"
+    vec2 Jitter(vec2 pixel, int index) {
+        vec2 p = pixel + float(index) * vec2(0.7548776662, 0.5698402910);
+        vec2 h = vec2(dot(p, vec2(12.9898, 78.233)), dot(p, vec2(39.3468, 11.1353)));
+        return fract(sin(h) * 43758.5453);
+    }
+"#;
+
+static MAIN: &str = r#"
+    // Averages samples by samples points of a grid over the pixel, each moved
+    // to a random point of its cell when jittered. A single sample stays at
+    // the center. Only the value of that sample is drawn when `values` is set,
+    // to be painted by the paint shader.
+    void main() {
+        vec2 pixel = floor(gl_FragCoord.xy);
+        if (values == 1) {
+            fragmentColor = Sample(pixel + 0.5);
+            return;
+        }
+
+        vec4 sum = vec4(0.);
+        for (int j = 0; j < MAX_SAMPLES; ++j) {
+            if (j >= samples) break;
+            for (int i = 0; i < MAX_SAMPLES; ++i) {
+                if (i >= samples) break;
+                vec2 offset = jitter == 1 && samples > 1 ? Jitter(pixel, j * samples + i) : vec2(0.5);
+                sum += Paint(Sample(pixel + (vec2(i, j) + offset) / float(samples)));
+            }
+        }
+        fragmentColor = sum / float(samples * samples);
+    }
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::CustomFormula;
+
+    const FORMULAS: [Formula; 7] = [
+        Formula::Quadratic,
+        Formula::Multibrot,
+        Formula::BurningShip,
+        Formula::Tricorn,
+        Formula::Celtic,
+        Formula::Buffalo,
+        Formula::Custom,
+    ];
+
+    // Every program the explorer builds. Formulas other than z^2 + c only
+    // have the single precision backend, and WebGL 1 no perturbation.
+    fn configs() -> Vec<ShaderConfig> {
+        let mut configs = Vec::new();
+        for version in [GlVersion::WebGl1, GlVersion::WebGl2] {
+            for formula in FORMULAS {
+                for precision in [
+                    Precision::Single,
+                    Precision::Double,
+                    Precision::Perturbation,
+                ] {
+                    for coloring in [Coloring::Smooth, Coloring::Distance, Coloring::Trap] {
+                        if (formula != Formula::Quadratic && precision != Precision::Single)
+                            || (version == GlVersion::WebGl1
+                                && precision == Precision::Perturbation)
+                        {
+                            continue;
+                        }
+                        configs.push(ShaderConfig {
+                            version,
+                            formula,
+                            custom: match formula {
+                                Formula::Custom => "(cmul(z, z) + c)".to_string(),
+                                _ => String::new(),
+                            },
+                            precision,
+                            coloring,
+                        });
+                    }
+                }
+            }
+        }
+        configs
+    }
+
+    // Programs whose whole source is kept under tests/fixtures/shaders, one for
+    // each backend and version, one custom formula and the paint shader. Run
+    // the tests with UPDATE_FIXTURES=1 to write them anew after changing the
+    // shaders.
+    fn snapshots() -> [(&'static str, &'static str, String); 6] {
+        let config = |version, formula, precision, coloring| {
+            fragment_shader(&ShaderConfig {
+                version,
+                formula,
+                custom: match formula {
+                    Formula::Custom => CustomFormula::parse("sin(z) * c + z^3").unwrap().glsl(),
+                    _ => String::new(),
+                },
+                precision,
+                coloring,
+            })
+        };
+        [
+            (
+                "webgl2_single.frag",
+                include_str!("../tests/fixtures/shaders/webgl2_single.frag"),
+                config(
+                    GlVersion::WebGl2,
+                    Formula::Quadratic,
+                    Precision::Single,
+                    Coloring::Smooth,
+                ),
+            ),
+            (
+                "webgl2_double.frag",
+                include_str!("../tests/fixtures/shaders/webgl2_double.frag"),
+                config(
+                    GlVersion::WebGl2,
+                    Formula::Quadratic,
+                    Precision::Double,
+                    Coloring::Distance,
+                ),
+            ),
+            (
+                "webgl2_perturbation.frag",
+                include_str!("../tests/fixtures/shaders/webgl2_perturbation.frag"),
+                config(
+                    GlVersion::WebGl2,
+                    Formula::Quadratic,
+                    Precision::Perturbation,
+                    Coloring::Trap,
+                ),
+            ),
+            (
+                "webgl1_single.frag",
+                include_str!("../tests/fixtures/shaders/webgl1_single.frag"),
+                config(
+                    GlVersion::WebGl1,
+                    Formula::Quadratic,
+                    Precision::Single,
+                    Coloring::Smooth,
+                ),
+            ),
+            (
+                "webgl2_custom.frag",
+                include_str!("../tests/fixtures/shaders/webgl2_custom.frag"),
+                config(
+                    GlVersion::WebGl2,
+                    Formula::Custom,
+                    Precision::Single,
+                    Coloring::Smooth,
+                ),
+            ),
+            (
+                "paint.frag",
+                include_str!("../tests/fixtures/shaders/paint.frag"),
+                paint_shader(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn matches_the_snapshots() {
+        let update = std::env::var_os("UPDATE_FIXTURES").is_some();
+        for (name, snapshot, source) in snapshots() {
+            if update {
+                let path = format!(
+                    "{}/tests/fixtures/shaders/{name}",
+                    env!("CARGO_MANIFEST_DIR")
+                );
+                std::fs::write(path, &source).unwrap();
+            } else {
+                assert!(
+                    source == snapshot,
+                    "{name} is out of date, run the tests with UPDATE_FIXTURES=1"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fills_every_marker() {
+        for config in configs() {
+            let source = fragment_shader(&config);
+            assert!(!source.contains('$'), "{config:?}:\n{source}");
+        }
+    }
+
+    #[test]
+    fn starts_with_the_version() {
+        for config in configs() {
+            let source = fragment_shader(&config);
+            match config.version {
+                GlVersion::WebGl1 => {
+                    assert!(!source.contains("#version"), "{config:?}");
+                    assert!(source.contains("#define texture texture2D"), "{config:?}");
+                    assert!(!source.contains("uint"), "{config:?}");
+                }
+                // Nothing may come before the version directive.
+                GlVersion::WebGl2 => {
+                    assert!(source.starts_with("#version 300 es\n"), "{config:?}");
+                    assert!(source.contains("out vec4 fragmentColor;"), "{config:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn balances_brackets() {
+        for config in configs() {
+            let source = fragment_shader(&config);
+            let mut depth = 0;
+            // Comments may mention ranges such as [0, 1).
+            let code = source.lines().map(|line| line.split("//").next().unwrap());
+            for c in code.flat_map(str::chars) {
+                match c {
+                    '{' | '(' => depth += 1,
+                    '}' | ')' => depth -= 1,
+                    _ => {}
+                }
+                assert!(depth >= 0, "{config:?}");
+            }
+            assert_eq!(depth, 0, "{config:?}");
+            assert_eq!(source.matches("void main()").count(), 1, "{config:?}");
+        }
+    }
+
+    #[test]
+    fn interpolates_constants() {
+        let source = fragment_shader(&configs()[0]);
+        for constant in [
+            format!("const int MAX_ITERATIONS = {MAX_ITERATIONS};"),
+            format!("const int MAX_SAMPLES = {MAX_SAMPLES};"),
+            "const float PALETTE_SIZE = 256.0;".to_string(),
+            "const float PALETTE_PERIOD = 16.0;".to_string(),
+        ] {
+            assert!(source.contains(&constant), "{constant}");
+        }
+    }
+
+    #[test]
+    fn holds_only_what_the_config_draws() {
+        for config in configs() {
+            let source = fragment_shader(&config);
+            let has = |text: &str| source.contains(text);
+
+            assert!(has(match config.formula {
+                Formula::Quadratic => "return cmul(z, z) + c;",
+                Formula::Multibrot => "return Power(z, power) + c;",
+                Formula::BurningShip => "abs(2. * z.x * z.y)",
+                Formula::Tricorn => "-2. * z.x * z.y",
+                Formula::Celtic => "abs(z.x * z.x - z.y * z.y)",
+                Formula::Buffalo => "return abs(cmul(z, z)) + c;",
+                Formula::Custom => "return (cmul(z, z) + c);",
+            }));
+            assert_eq!(
+                has("InteriorShade("),
+                config.formula == Formula::Quadratic,
+                "{config:?}"
+            );
+
+            assert_eq!(
+                has("log_derivative ="),
+                config.coloring == Coloring::Distance,
+                "{config:?}"
+            );
+            assert_eq!(
+                has("TrapDistance("),
+                config.coloring == Coloring::Trap,
+                "{config:?}"
+            );
+
+            assert_eq!(
+                has("PerturbedEscape("),
+                config.precision == Precision::Perturbation,
+                "{config:?}"
+            );
+        }
+    }
+}
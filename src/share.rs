@@ -0,0 +1,441 @@
+use std::str::FromStr;
+
+use dashu_float::{round::mode::Zero, FBig};
+
+use crate::{
+    expression::CustomFormula, palette::DEFAULT_PALETTE, viewport::MIN_HALF_WIDTH, Formula,
+    Fractal, Precision, MAX_ITERATIONS, MAX_POWER, MIN_POWER,
+};
+
+// Bumped whenever the format changes. Links of older versions must still be
+// decoded.
+pub const VERSION: u32 = 5;
+
+// Decimal places kept in the center beyond those needed to tell apart points
+// half a view apart, so that the restored view is off by well under a pixel.
+const EXTRA_DIGITS: i32 = 6;
+
+// What is needed to restore a view from a link.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SharedView {
+    pub fractal: Fractal,
+    pub center: [FBig; 2],
+    pub half_width: f64,
+    pub rotation: f64,
+    pub iterations: i32,
+    pub c: [f32; 2],
+    pub formula: Formula,
+    pub power: f32,
+    // Only written for `Formula::Custom`.
+    pub custom: CustomFormula,
+    pub precision: Precision,
+    // Name of a built-in palette. Imported palettes are left out, as a link
+    // cannot carry them, and restoring the view keeps the current palette.
+    pub palette: Option<String>,
+    pub palette_speed: f32,
+    pub palette_offset: f32,
+}
+
+// Writes the view as `key=value` pairs joined by `&`, starting with the
+// version, e.g. `v=1&fractal=mandelbrot&re=-0.7&im=0&scale=1.8e0&...`.
+pub fn encode(view: &SharedView) -> String {
+    let places = (-view.half_width.log10()).ceil() as i32 + EXTRA_DIGITS;
+    let mut pairs = vec![
+        ("v", VERSION.to_string()),
+        ("fractal", fractal_name(view.fractal).to_string()),
+        ("re", to_decimal(&view.center[0], places)),
+        ("im", to_decimal(&view.center[1], places)),
+        ("scale", format!("{:e}", view.half_width)),
+        ("rotation", view.rotation.to_string()),
+        ("iterations", view.iterations.to_string()),
+        ("julia", format!("{},{}", view.c[0], view.c[1])),
+        ("formula", formula_name(view.formula).to_string()),
+        ("power", view.power.to_string()),
+        ("precision", precision_name(view.precision).to_string()),
+        ("speed", view.palette_speed.to_string()),
+        ("offset", view.palette_offset.to_string()),
+    ];
+    if let Some(palette) = &view.palette {
+        pairs.push(("palette", palette.clone()));
+    }
+    if view.formula == Formula::Custom {
+        pairs.push(("expression", escape(view.custom.source())));
+    }
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// Reads a view written by `encode`, with or without the leading `#`. Unknown
+// keys are ignored.
+pub fn decode(hash: &str) -> Result<SharedView, String> {
+    let pairs = hash
+        .strip_prefix('#')
+        .unwrap_or(hash)
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            pair.split_once('=')
+                .ok_or_else(|| format!("expected `key=value`, found `{pair}`"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let get = |key: &str| {
+        pairs
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|&(_, value)| value)
+            .ok_or_else(|| format!("missing `{key}`"))
+    };
+
+    let version = parse::<u32>("v", get("v")?)?;
+    if version == 0 || version > VERSION {
+        return Err(format!("unsupported version {version}"));
+    }
+
+    let fractal = match get("fractal")? {
+        "mandelbrot" => Fractal::Mandelbrot,
+        "julia" => Fractal::Julia,
+        name => return Err(format!("unknown fractal `{name}`")),
+    };
+    let precision = match get("precision")? {
+        "single" => Precision::Single,
+        "double" => Precision::Double,
+        "perturbation" => Precision::Perturbation,
+        name => return Err(format!("unknown precision `{name}`")),
+    };
+    let c = get("julia")?;
+    let (re_c, im_c) = c
+        .split_once(',')
+        .ok_or_else(|| format!("expected `re,im` for `julia`, found `{c}`"))?;
+    // Version 1 had no palettes, and always used the default one.
+    let (palette, palette_speed, palette_offset) = if version >= 2 {
+        (
+            match get("palette") {
+                Ok(palette) => Some(palette.to_string()),
+                // Version 2 always named one.
+                Err(_) if version >= 3 => None,
+                Err(err) => return Err(err),
+            },
+            parse::<f32>("speed", get("speed")?)?,
+            parse::<f32>("offset", get("offset")?)?,
+        )
+    } else {
+        (Some(DEFAULT_PALETTE.to_string()), 1., 0.)
+    };
+    if !palette_speed.is_finite() {
+        return Err(format!("`speed` must be finite, found {palette_speed}"));
+    }
+    if !palette_offset.is_finite() {
+        return Err(format!("`offset` must be finite, found {palette_offset}"));
+    }
+    // Versions 1 to 3 only had z^2 + c.
+    let (formula, power) = if version >= 4 {
+        let formula = match get("formula")? {
+            "quadratic" => Formula::Quadratic,
+            "multibrot" => Formula::Multibrot,
+            "burning_ship" => Formula::BurningShip,
+            "tricorn" => Formula::Tricorn,
+            "celtic" => Formula::Celtic,
+            "buffalo" => Formula::Buffalo,
+            // Version 4 had no custom formulas.
+            "custom" if version >= 5 => Formula::Custom,
+            name => return Err(format!("unknown formula `{name}`")),
+        };
+        let power = parse::<f32>("power", get("power")?)?;
+        if !(MIN_POWER..=MAX_POWER).contains(&power) {
+            return Err(format!(
+                "`power` must be between {MIN_POWER} and {MAX_POWER}, found {power}"
+            ));
+        }
+        (formula, power)
+    } else {
+        (Formula::Quadratic, 2.)
+    };
+    let custom = if formula == Formula::Custom {
+        let source = unescape(get("expression")?)?;
+        CustomFormula::parse(&source).map_err(|err| format!("invalid `expression`: {err}"))?
+    } else {
+        CustomFormula::default()
+    };
+    let half_width = parse::<f64>("scale", get("scale")?)?;
+    // As deep as zooms go, and no deeper.
+    if !(half_width.is_finite() && half_width >= MIN_HALF_WIDTH) {
+        return Err(format!(
+            "`scale` must be at least {MIN_HALF_WIDTH:e}, found {half_width}"
+        ));
+    }
+    let rotation = parse::<f64>("rotation", get("rotation")?)?;
+    if !rotation.is_finite() {
+        return Err(format!("`rotation` must be finite, found {rotation}"));
+    }
+    // Links come from anyone, and a huge count would stall the page.
+    let iterations = parse::<i32>("iterations", get("iterations")?)?;
+    if !(1..=MAX_ITERATIONS).contains(&iterations) {
+        return Err(format!(
+            "`iterations` must be between 1 and {MAX_ITERATIONS}, found {iterations}"
+        ));
+    }
+    let c = [parse::<f32>("julia", re_c)?, parse("julia", im_c)?];
+    if !c.iter().all(|x| x.is_finite()) {
+        return Err(format!("`julia` must be finite, found {c:?}"));
+    }
+
+    Ok(SharedView {
+        fractal,
+        center: [
+            from_decimal("re", get("re")?)?,
+            from_decimal("im", get("im")?)?,
+        ],
+        half_width,
+        rotation,
+        iterations,
+        c,
+        formula,
+        power,
+        custom,
+        precision,
+        palette,
+        palette_speed,
+        palette_offset,
+    })
+}
+
+// Percent-encodes all but letters, digits and `-._~`, so that values can
+// hold any text.
+fn escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+fn unescape(value: &str) -> Result<String, String> {
+    let invalid = || format!("invalid percent-encoding in `{value}`");
+    let mut bytes = Vec::new();
+    let mut rest = value.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = after.get(..2).ok_or_else(invalid)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            rest = &after[2..];
+        } else {
+            bytes.push(byte);
+            rest = after;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid `{key}`: `{value}`"))
+}
+
+fn fractal_name(fractal: Fractal) -> &'static str {
+    match fractal {
+        Fractal::Mandelbrot => "mandelbrot",
+        Fractal::Julia => "julia",
+    }
+}
+
+fn formula_name(formula: Formula) -> &'static str {
+    match formula {
+        Formula::Quadratic => "quadratic",
+        Formula::Multibrot => "multibrot",
+        Formula::BurningShip => "burning_ship",
+        Formula::Tricorn => "tricorn",
+        Formula::Celtic => "celtic",
+        Formula::Buffalo => "buffalo",
+        Formula::Custom => "custom",
+    }
+}
+
+fn precision_name(precision: Precision) -> &'static str {
+    match precision {
+        Precision::Single => "single",
+        Precision::Double => "double",
+        Precision::Perturbation => "perturbation",
+    }
+}
+
+// Rounds `x` to `places` decimal places, as significant digits are what the
+// conversion takes.
+fn to_decimal(x: &FBig, places: i32) -> String {
+    let magnitude = x.to_f64().value().abs().log10().floor() as i32 + 1;
+    let digits = places + magnitude;
+    if digits <= 0 {
+        return "0".to_string();
+    }
+    x.clone()
+        .with_base_and_precision::<10>(digits as usize)
+        .value()
+        .to_string()
+}
+
+fn from_decimal(key: &str, value: &str) -> Result<FBig, String> {
+    let decimal =
+        FBig::<Zero, 10>::from_str(value).map_err(|_| format!("invalid `{key}`: `{value}`"))?;
+    Ok(decimal.with_base::<2>().value())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view() -> SharedView {
+        SharedView {
+            fractal: Fractal::Julia,
+            center: [
+                from_decimal("re", "-0.743643887037151").unwrap(),
+                from_decimal("im", "0.131825904205330").unwrap(),
+            ],
+            half_width: 1.5e-9,
+            rotation: 0.25,
+            iterations: 2000,
+            c: [-0.8, 0.156],
+            formula: Formula::Multibrot,
+            power: 3.,
+            custom: CustomFormula::default(),
+            precision: Precision::Double,
+            palette: Some("fire".to_string()),
+            palette_speed: 2.5,
+            palette_offset: 0.125,
+        }
+    }
+
+    fn replace(hash: &str, key: &str, value: &str) -> String {
+        hash.split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((k, _)) if k == key => format!("{key}={value}"),
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    #[test]
+    fn round_trips() {
+        let view = view();
+        let decoded = decode(&format!("#{}", encode(&view))).unwrap();
+        // The center is rounded to well under a pixel.
+        for (decoded, center) in decoded.center.iter().zip(&view.center) {
+            let error = (decoded.to_f64().value() - center.to_f64().value()).abs();
+            assert!(error < view.half_width * 1e-3);
+        }
+        assert_eq!(
+            SharedView {
+                center: view.center.clone(),
+                ..decoded
+            },
+            view
+        );
+    }
+
+    #[test]
+    fn round_trips_custom_formulas() {
+        let view = SharedView {
+            formula: Formula::Custom,
+            custom: CustomFormula::parse("z^3 + sin(c)*z + c").unwrap(),
+            ..view()
+        };
+        let hash = encode(&view);
+        assert!(hash.contains("expression=z%5E3%20%2B%20sin%28c%29%2Az%20%2B%20c"));
+        assert_eq!(decode(&hash).unwrap().custom, view.custom);
+        // Custom formulas came with version 5.
+        assert!(decode(&replace(&hash, "v", "4")).is_err());
+    }
+
+    #[test]
+    fn leaves_out_imported_palettes() {
+        let view = SharedView {
+            palette: None,
+            ..view()
+        };
+        let hash = encode(&view);
+        assert!(!hash.contains("palette="));
+        assert_eq!(decode(&hash).unwrap().palette, None);
+        assert!(decode(&replace(&hash, "v", "2")).is_err());
+    }
+
+    #[test]
+    fn decodes_version_1() {
+        let view =
+            decode("v=1&fractal=mandelbrot&re=-0.7&im=0&scale=1.8e0&rotation=0&iterations=100&julia=-0.8,0.156&precision=single")
+                .unwrap();
+        assert_eq!(view.palette.as_deref(), Some(DEFAULT_PALETTE));
+        assert_eq!(view.formula, Formula::Quadratic);
+        assert_eq!(view.iterations, 100);
+    }
+
+    #[test]
+    fn rejects_invalid_views() {
+        let hash = encode(&view());
+        for (key, value) in [
+            ("v", "0"),
+            ("v", "99"),
+            ("fractal", "koch"),
+            ("precision", "quad"),
+            ("formula", "newton"),
+            ("power", "100"),
+            ("scale", "-1"),
+            ("scale", "inf"),
+            ("scale", "1e-300"),
+            ("rotation", "NaN"),
+            ("rotation", "inf"),
+            ("iterations", "0"),
+            ("iterations", "2147483647"),
+            ("iterations", "many"),
+            ("julia", "1"),
+            ("julia", "NaN,0"),
+            ("re", "east"),
+            ("speed", "NaN"),
+            ("speed", "inf"),
+            ("speed", "fast"),
+            ("offset", "-inf"),
+            ("offset", "NaN"),
+        ] {
+            let hash = replace(&hash, key, value);
+            assert!(decode(&hash).is_err(), "accepted {key}={value}");
+        }
+        assert!(decode(&hash.replace("&iterations=2000", "")).is_err());
+        assert!(decode("v=3&fractal").is_err());
+    }
+
+    #[test]
+    fn decodes_palette_speed_and_offset() {
+        let hash = encode(&view());
+        let view = decode(&replace(&replace(&hash, "speed", "-0.5"), "offset", "3.25")).unwrap();
+        assert_eq!((view.palette_speed, view.palette_offset), (-0.5, 3.25));
+        // Version 1 had neither.
+        let view = decode(&replace(
+            &hash.replace("&speed=2.5&offset=0.125", ""),
+            "v",
+            "1",
+        ))
+        .unwrap();
+        assert_eq!((view.palette_speed, view.palette_offset), (1., 0.));
+        assert!(decode(&hash.replace("&speed=2.5", "")).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        let hash = encode(&SharedView {
+            formula: Formula::Custom,
+            custom: CustomFormula::parse("z^2 + c").unwrap(),
+            ..view()
+        });
+        for expression in ["%zz", "%E2%82", "z%2B%2B"] {
+            let hash = replace(&hash, "expression", expression);
+            assert!(decode(&hash).is_err(), "accepted {expression}");
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::TrapShape;
+
+// Geometry of the orbit trap, in the complex plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrbitTrap {
+    pub shape: TrapShape,
+    pub center: [f32; 2],
+    // Counterclockwise from the real axis, in radians, for lines and crosses.
+    pub angle: f32,
+    // Only used by circles.
+    pub radius: f32,
+    // Distance from the trap one pass through the palette takes.
+    pub width: f32,
+}
+
+impl OrbitTrap {
+    // Unit vector along the trap's line, or the first of its cross.
+    pub fn direction(&self) -> [f32; 2] {
+        let (sin, cos) = self.angle.sin_cos();
+        [cos, sin]
+    }
+
+    // Distance from `z` to the trap, as `TrapDistance()` in the fragment
+    // shader.
+    pub fn distance(&self, z: [f32; 2]) -> f32 {
+        let d = [z[0] - self.center[0], z[1] - self.center[1]];
+        let [cos, sin] = self.direction();
+        let along = (d[0] * cos + d[1] * sin).abs();
+        let across = (d[1] * cos - d[0] * sin).abs();
+        match self.shape {
+            TrapShape::Point => d[0].hypot(d[1]),
+            TrapShape::Line => across,
+            TrapShape::Circle => (d[0].hypot(d[1]) - self.radius).abs(),
+            TrapShape::Cross | TrapShape::Stalks => along.min(across),
+        }
+    }
+}
+
+impl Default for OrbitTrap {
+    fn default() -> OrbitTrap {
+        OrbitTrap {
+            shape: TrapShape::Point,
+            center: [0., 0.],
+            angle: 0.,
+            radius: 1.,
+            width: 0.5,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, SQRT_2};
+
+    use super::*;
+
+    fn trap(shape: TrapShape, center: [f32; 2], angle: f32) -> OrbitTrap {
+        OrbitTrap {
+            shape,
+            center,
+            angle,
+            radius: 2.,
+            ..OrbitTrap::default()
+        }
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-6, "{a} != {b}");
+    }
+
+    #[test]
+    fn direction_follows_the_angle() {
+        assert_eq!(trap(TrapShape::Line, [0., 0.], 0.).direction(), [1., 0.]);
+        let [x, y] = trap(TrapShape::Line, [0., 0.], FRAC_PI_2).direction();
+        assert_close(x, 0.);
+        assert_close(y, 1.);
+    }
+
+    #[test]
+    fn measures_the_distance_to_each_shape() {
+        let point = trap(TrapShape::Point, [1., 1.], 0.);
+        assert_close(point.distance([4., 5.]), 5.);
+        assert_close(point.distance([1., 1.]), 0.);
+
+        // Distances across the line, however far along it.
+        let line = trap(TrapShape::Line, [0., 1.], 0.);
+        assert_close(line.distance([3., 4.]), 3.);
+        assert_close(line.distance([-100., 1.]), 0.);
+        let diagonal = trap(TrapShape::Line, [0., 0.], FRAC_PI_4);
+        assert_close(diagonal.distance([1., 1.]), 0.);
+        assert_close(diagonal.distance([1., -1.]), SQRT_2);
+
+        // The closer of the two lines.
+        for shape in [TrapShape::Cross, TrapShape::Stalks] {
+            let cross = trap(shape, [0., 0.], 0.);
+            assert_close(cross.distance([3., 4.]), 3.);
+            assert_close(cross.distance([5., -0.5]), 0.5);
+            assert_close(cross.distance([0., 5.]), 0.);
+        }
+
+        let circle = trap(TrapShape::Circle, [0., 0.], 0.);
+        assert_close(circle.distance([3., 4.]), 3.);
+        assert_close(circle.distance([0., 0.]), 2.);
+        assert_close(circle.distance([0., -2.]), 0.);
+    }
+}
@@ -0,0 +1,255 @@
+use dashu_float::FBig;
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+
+// Smallest half width zooms stop at, 2^64 above the smallest normal float, so
+// that the width of a pixel is still a normal float with bits to spare.
+pub const MIN_HALF_WIDTH: f64 = f64::MIN_POSITIVE * 18446744073709551616.;
+
+// The visible region of the complex plane. Screen points are given in pixels
+// with the origin at the top left, as in mouse events.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Viewport {
+    // Kept with enough bits to tell neighboring pixels apart at any zoom.
+    #[wasm_bindgen(skip)]
+    pub center: [FBig; 2],
+    // Half of the visible width along the real axis.
+    pub half_width: f64,
+    // Width divided by height of the canvas.
+    pub ratio: f64,
+    // Counterclockwise, in radians.
+    pub rotation: f64,
+}
+
+#[wasm_bindgen]
+impl Viewport {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        re: f64,
+        im: f64,
+        half_width: f64,
+        width: f64,
+        height: f64,
+    ) -> Result<Viewport, Error> {
+        Ok(Viewport {
+            center: [to_fbig(re)?, to_fbig(im)?],
+            half_width,
+            ratio: width / height,
+            rotation: 0.,
+        })
+    }
+
+    pub fn half_height(&self) -> f64 {
+        self.half_width / self.ratio
+    }
+
+    pub fn resize(&mut self, width: f64, height: f64) {
+        self.ratio = width / height;
+    }
+
+    // Scales the view by `factor` while keeping the point under `(x, y)` fixed.
+    // Zooming in stops at `MIN_HALF_WIDTH`.
+    pub fn zoom_at(
+        &mut self,
+        factor: f64,
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    ) -> Result<(), Error> {
+        let [re, im] = self.to_delta(x - width / 2., y - height / 2., width, height);
+        let half_width = self.half_width;
+        // Zoomed first, so that the center is offset with the new precision.
+        self.half_width = (half_width * factor).max(MIN_HALF_WIDTH);
+        let factor = self.half_width / half_width;
+        self.offset([re * (1. - factor), im * (1. - factor)])
+            .inspect_err(|_| self.half_width = half_width)
+    }
+
+    // Moves the view so that the content follows a drag of `(dx, dy)` pixels.
+    pub fn pan(&mut self, dx: f64, dy: f64, width: f64, height: f64) -> Result<(), Error> {
+        let delta = self.to_delta(-dx, -dy, width, height);
+        self.offset(delta)
+    }
+
+    pub fn rotate(&mut self, angle: f64) {
+        self.rotation += angle;
+    }
+
+    // Number of bits the center is stored with at the current zoom.
+    pub fn precision(&self) -> usize {
+        (64. - self.half_width.log2()).max(64.) as usize
+    }
+}
+
+impl Viewport {
+    pub fn center_f64(&self) -> [f64; 2] {
+        [
+            self.center[0].to_f64().value(),
+            self.center[1].to_f64().value(),
+        ]
+    }
+
+    // Half extents of the view, as passed to the `scale` uniform.
+    pub fn scale(&self) -> [f64; 2] {
+        [self.half_width, self.half_height()]
+    }
+
+    pub fn to_complex(&self, x: f64, y: f64, width: f64, height: f64) -> [f64; 2] {
+        let [re_center, im_center] = self.center_f64();
+        let [re, im] = self.to_delta(x - width / 2., y - height / 2., width, height);
+        [re_center + re, im_center + im]
+    }
+
+    // Converts a distance on screen into a distance on the complex plane.
+    pub fn to_delta(&self, dx: f64, dy: f64, width: f64, height: f64) -> [f64; 2] {
+        let u = dx / (width / 2.) * self.half_width;
+        let v = -dy / (height / 2.) * self.half_height();
+        let (sin, cos) = self.rotation.sin_cos();
+        [u * cos - v * sin, u * sin + v * cos]
+    }
+
+    // Moves the center by `delta` without losing the precision of the center.
+    // Leaves the view as it was if `delta` is not finite.
+    pub fn offset(&mut self, delta: [f64; 2]) -> Result<(), Error> {
+        let delta = [to_fbig(delta[0])?, to_fbig(delta[1])?];
+        let precision = self.precision();
+        for (center, delta) in self.center.iter_mut().zip(delta) {
+            let widened = center.clone().with_precision(precision).value();
+            *center = widened + delta;
+        }
+        Ok(())
+    }
+}
+
+// Fails on NaN, and on infinities, which `FBig` only holds to report overflows
+// and cannot compute with.
+pub fn to_fbig(x: f64) -> Result<FBig, Error> {
+    x.is_finite()
+        .then(|| FBig::try_from(x).ok())
+        .flatten()
+        .ok_or_else(|| Error::InvalidArgument(format!("{x} is not a finite number")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+
+    const WIDTH: f64 = 400.;
+    const HEIGHT: f64 = 300.;
+
+    fn assert_close(a: [f64; 2], b: [f64; 2]) {
+        assert!(
+            (a[0] - b[0]).abs() < 1e-12 && (a[1] - b[1]).abs() < 1e-12,
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn maps_pixels_to_complex() {
+        let viewport = Viewport::new(-0.5, 0.25, 2., WIDTH, HEIGHT).unwrap();
+        assert_close(
+            viewport.to_complex(WIDTH / 2., HEIGHT / 2., WIDTH, HEIGHT),
+            [-0.5, 0.25],
+        );
+        assert_close(
+            viewport.to_complex(WIDTH, HEIGHT / 2., WIDTH, HEIGHT),
+            [1.5, 0.25],
+        );
+        // Screen rows go down while the imaginary axis goes up.
+        assert_close(
+            viewport.to_complex(WIDTH / 2., 0., WIDTH, HEIGHT),
+            [-0.5, 1.75],
+        );
+    }
+
+    #[test]
+    fn maps_pixels_to_complex_under_rotation() {
+        let mut viewport = Viewport::new(0., 0., 2., WIDTH, HEIGHT).unwrap();
+        viewport.rotate(FRAC_PI_2);
+        assert_close(
+            viewport.to_complex(WIDTH / 2., HEIGHT / 2., WIDTH, HEIGHT),
+            [0., 0.],
+        );
+        // The right edge turns a quarter counterclockwise, to the top.
+        assert_close(
+            viewport.to_complex(WIDTH, HEIGHT / 2., WIDTH, HEIGHT),
+            [0., 2.],
+        );
+        assert_close(
+            viewport.to_complex(WIDTH / 2., 0., WIDTH, HEIGHT),
+            [-1.5, 0.],
+        );
+    }
+
+    #[test]
+    fn zoom_keeps_the_point_under_the_cursor() {
+        let mut viewport = Viewport::new(-0.7, 0.1, 1.8, WIDTH, HEIGHT).unwrap();
+        viewport.rotate(0.3);
+        let (x, y) = (123., 45.);
+        let before = viewport.to_complex(x, y, WIDTH, HEIGHT);
+        viewport.zoom_at(0.8, x, y, WIDTH, HEIGHT).unwrap();
+        assert_close(viewport.to_complex(x, y, WIDTH, HEIGHT), before);
+        assert_eq!(viewport.half_width, 1.8 * 0.8);
+    }
+
+    #[test]
+    fn zoom_stops_at_the_smallest_half_width() {
+        let mut viewport = Viewport::new(-0.7, 0.1, 1.8, WIDTH, HEIGHT).unwrap();
+        let (x, y) = (123., 45.);
+        for _ in 0..2000 {
+            viewport.zoom_at(0.5, x, y, WIDTH, HEIGHT).unwrap();
+        }
+        assert_eq!(viewport.half_width, MIN_HALF_WIDTH);
+        assert_eq!(viewport.precision(), 1022);
+
+        // Still keeps the point under the cursor once it stops.
+        let before = viewport.to_delta(x - WIDTH / 2., y - HEIGHT / 2., WIDTH, HEIGHT);
+        let center = viewport.center.clone();
+        viewport.zoom_at(0.5, x, y, WIDTH, HEIGHT).unwrap();
+        assert_eq!(viewport.center, center);
+        assert_eq!(
+            viewport.to_delta(x - WIDTH / 2., y - HEIGHT / 2., WIDTH, HEIGHT),
+            before
+        );
+    }
+
+    #[test]
+    fn pan_follows_the_drag() {
+        let mut viewport = Viewport::new(0., 0., 2., WIDTH, HEIGHT).unwrap();
+        let grabbed = viewport.to_complex(100., 100., WIDTH, HEIGHT);
+        viewport.pan(30., -20., WIDTH, HEIGHT).unwrap();
+        assert_close(viewport.to_complex(130., 80., WIDTH, HEIGHT), grabbed);
+    }
+
+    #[test]
+    fn precision_grows_with_depth() {
+        let mut viewport = Viewport::new(0., 0., 2., WIDTH, HEIGHT).unwrap();
+        assert_eq!(viewport.precision(), 64);
+        viewport.half_width = 2f64.powi(-100);
+        assert_eq!(viewport.precision(), 164);
+        viewport.half_width = 2f64.powi(-1000);
+        assert_eq!(viewport.precision(), 1064);
+    }
+
+    #[test]
+    fn to_fbig_rejects_non_finite_numbers() {
+        assert_eq!(to_fbig(0.5).unwrap().to_f64().value(), 0.5);
+        for x in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(to_fbig(x).is_err());
+        }
+        assert!(Viewport::new(f64::NAN, 0., 1., WIDTH, HEIGHT).is_err());
+    }
+
+    #[test]
+    fn failed_zoom_leaves_the_view() {
+        let mut viewport = Viewport::new(0., 0., 2., WIDTH, HEIGHT).unwrap();
+        let before = viewport.clone();
+        assert!(viewport.zoom_at(0.5, f64::NAN, 0., WIDTH, HEIGHT).is_err());
+        assert_eq!(viewport, before);
+    }
+}
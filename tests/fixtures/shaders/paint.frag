@@ -0,0 +1,28 @@
+
+    precision highp float;
+
+    #define texture texture2D
+
+    const float PALETTE_SIZE = 256.0;
+
+    uniform highp sampler2D	values;
+    uniform vec2	resolution;
+
+    uniform sampler2D	palette;
+    uniform float	palette_offset;
+
+    vec4 Paint(vec4 value) {
+        // Offset by half a texel so that filtering blends neighboring colors
+        // the way the palette does between its stops.
+        vec2 position = fract(palette_offset + value.xy) + 0.5 / PALETTE_SIZE;
+        vec3 color = mix(
+            texture(palette, vec2(position.x, 0.5)).rgb,
+            texture(palette, vec2(position.y, 0.5)).rgb,
+            value.z
+        );
+        return vec4(color * value.w, 1.);
+    }
+
+    void main() {
+        gl_FragColor = Paint(texture(values, gl_FragCoord.xy / resolution));
+    }
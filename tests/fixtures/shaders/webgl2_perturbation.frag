@@ -0,0 +1,397 @@
+#version 300 es
+    precision highp float;
+    precision highp int;
+
+    const int MAX_ITERATIONS = 1048576;
+    const int MAX_SAMPLES = 8;
+    const float PALETTE_SIZE = 256.0;
+    const float PALETTE_PERIOD = 16.0;
+
+    uniform vec2	center;
+    uniform vec2	center_lo;
+    uniform vec2	scale;
+    uniform vec2	scale_lo;
+    uniform float	rotation;
+
+    uniform highp sampler2D	orbit;
+    uniform int		orbit_length;
+    uniform float	delta_scale;
+    uniform int		delta_exponent;
+
+    uniform int		series_skip;
+    uniform vec2	series[3];
+    uniform int		series_exponent;
+
+    uniform vec2	resolution;
+    uniform int		iterations;
+
+    uniform int		fractal;
+    uniform vec2	c;
+    uniform float	power;
+
+    uniform int		samples;
+    uniform int		jitter;
+    uniform int		values;
+
+    uniform int		interior;
+
+    uniform int		trap_shape;
+    uniform vec2	trap_center;
+    uniform vec2	trap_direction;
+    uniform float	trap_radius;
+    uniform float	trap_width;
+
+    uniform sampler2D	palette;
+    uniform float	palette_speed;
+    uniform float	palette_offset;
+
+    out vec4 fragmentColor;
+
+    vec2 cmul(vec2 a, vec2 b) {
+        return vec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+    }
+
+    vec2 cdiv(vec2 a, vec2 b) {
+        return vec2(dot(a, b), a.y * b.x - a.x * b.y) / dot(b, b);
+    }
+
+    vec2 cexp(vec2 a) {
+        return exp(a.x) * vec2(cos(a.y), sin(a.y));
+    }
+
+    vec2 clog(vec2 a) {
+        return vec2(log(length(a)), atan(a.y, a.x));
+    }
+
+    vec2 csin(vec2 a) {
+        return vec2(sin(a.x) * cosh(a.y), cos(a.x) * sinh(a.y));
+    }
+
+    vec2 ccos(vec2 a) {
+        return vec2(cos(a.x) * cosh(a.y), -sin(a.x) * sinh(a.y));
+    }
+
+    vec2 cpow(vec2 a, vec2 b) {
+        return a == vec2(0.) ? a : cexp(cmul(b, clog(a)));
+    }
+
+    vec2 conj(vec2 a) {
+        return vec2(a.x, -a.y);
+    }
+
+    // z^n, by repeated multiplication when n is a small positive integer.
+    vec2 Power(vec2 z, float n) {
+        if (fract(n) == 0. && n >= 1. && n <= 64.) {
+            vec2 w = z;
+            for (int k = 1; k < 64; ++k) {
+                if (k >= int(n)) break;
+                w = cmul(w, z);
+            }
+            return w;
+        }
+        if (z == vec2(0.)) return z;
+        float angle = atan(z.y, z.x) * n;
+        return pow(length(z), n) * vec2(cos(angle), sin(angle));
+    }
+
+    // One iteration of the formula.
+    vec2 Step(vec2 z, vec2 c) {
+        return cmul(z, z) + c;
+    }
+
+    // Derivative of Step with respect to z, times dz.
+    vec2 StepDerivative(vec2 z, vec2 c, vec2 dz) {
+        return 2. * cmul(z, dz);
+    }
+
+    bool Escaped(vec2 z) {
+        return dot(z, z) > 4.0;
+    }
+
+    // The distance the orbit came closest to the trap by before escaping.
+    float trap;
+
+    // Distance from z to the orbit trap: a point, a line along
+    // trap_direction, a circle, or a cross of two lines, which Pickover stalks
+    // are too.
+    float TrapDistance(vec2 z) {
+        vec2 d = z - trap_center;
+        float along = abs(dot(d, trap_direction));
+        float across = abs(dot(d, vec2(-trap_direction.y, trap_direction.x)));
+        if (trap_shape == 0) return length(d);
+        if (trap_shape == 1) return across;
+        if (trap_shape == 2) return abs(length(d) - trap_radius);
+        return min(along, across);
+    }
+
+    // The pixel's point, as near as a float gets to it.
+    vec2 point;
+    // Log2 of the distance between pixels, in the units the derivative is
+    // taken in.
+    float log_pixel;
+
+    vec2 Rotate(vec2 v) {
+        return vec2(
+            v.x * cos(rotation) - v.y * sin(rotation),
+            v.x * sin(rotation) + v.y * cos(rotation)
+        );
+    }
+
+    vec2 Orbit(int m) {
+        return texelFetch(orbit, ivec2(m % 1024, m / 1024), 0).xy;
+    }
+
+    // One iteration of the derivative in PerturbedEscape.
+    void Derive(vec2 z, float mandelbrot, inout vec2 derivative, inout int derivative_exponent) {
+        derivative = 2.0 * cmul(z, derivative)
+            + vec2(mandelbrot * exp2(float(delta_exponent - derivative_exponent)), 0.);
+        if (max(abs(derivative.x), abs(derivative.y)) > 4294967296.) {
+            derivative /= 4294967296.;
+            derivative_exponent += 32;
+        }
+    }
+
+    // Iterates the difference dz of a pixel from the reference orbit of
+    // z^2 + c, from iteration i at its point m. While dz is too small for a
+    // float it is kept in units of 2^exponent, and dc always is in units of
+    // 2^delta_exponent.
+    //
+    // The derivative of z with respect to the pixel's offset d is too small
+    // for a float at first, so it is kept in units of 2^derivative_exponent,
+    // and has 2^delta_exponent added every iteration when `mandelbrot` is 1.
+    //
+    // The iterations skipped by the series approximation are left out of the
+    // orbit trap.
+    vec3 PerturbedEscape(
+        int i,
+        int m,
+        vec2 dz,
+        int exponent,
+        vec2 dc,
+        vec2 derivative,
+        int derivative_exponent,
+        float mandelbrot
+    ) {
+        trap = 1e20;
+        if (exponent >= -64) {
+            dz *= exp2(float(exponent));
+            dc *= exp2(float(delta_exponent));
+        }
+        for(; i <= iterations ; ++i) {
+            vec2 Z = Orbit(m);
+            if (exponent < -64) {
+                // dz is negligible next to Z here.
+                if (Escaped(Z)) {
+                    return vec3(Z, float(i));
+                }
+
+                trap = min(trap, TrapDistance(Z));
+                dz = 2.0 * cmul(Z, dz) + exp2(float(exponent)) * cmul(dz, dz)
+                    + dc * exp2(float(delta_exponent - exponent));
+                ++m;
+                if (max(abs(dz.x), abs(dz.y)) > 4294967296.) {
+                    dz /= 4294967296.;
+                    exponent += 32;
+                    if (exponent >= -64) {
+                        dz *= exp2(float(exponent));
+                        dc *= exp2(float(delta_exponent));
+                    }
+                }
+                continue;
+            }
+
+            vec2 z = Z + dz;
+            if (Escaped(z)) {
+                return vec3(z, float(i));
+            }
+
+            trap = min(trap, TrapDistance(z));
+            // Rebase onto the start of the reference orbit when the pixel is
+            // closer to it than to the current reference point, or when the
+            // reference has run out. This keeps dz small and avoids glitches.
+            vec2 rebased = z - Orbit(0);
+            if (dot(rebased, rebased) < dot(dz, dz) || m == orbit_length - 1) {
+                dz = rebased;
+                m = 0;
+                Z = Orbit(0);
+            }
+
+            dz = 2.0 * cmul(Z, dz) + cmul(dz, dz) + dc;
+            ++m;
+        }
+        vec2 z = exponent < -64 ? Orbit(m) : Orbit(m) + dz;
+        return vec3(z, 0.);
+    }
+
+    vec3 Iterate(vec2 uv) {
+        // Offset from the view center in units of 2^delta_exponent.
+        vec2 d = Rotate(uv * vec2(1., resolution.y / resolution.x)) * delta_scale;
+        point = center + d * exp2(float(delta_exponent));
+        log_pixel = log2(2. * delta_scale / resolution.x);
+        // The Mandelbrot orbit starts at 0, one point before the pixel.
+        int start = fractal == 1 ? 0 : 1;
+        vec2 dc = fractal == 1 ? vec2(0.) : d;
+        // Skip the iterations covered by the series approximation.
+        vec2 d2 = cmul(d, d);
+        vec2 dz = cmul(series[0], d) + cmul(series[1], d2) + cmul(series[2], cmul(d2, d));
+        // The derivative of the series with respect to d.
+        vec2 derivative = series[0] + 2. * cmul(series[1], d) + 3. * cmul(series[2], d2);
+        return PerturbedEscape(
+            1 + series_skip,
+            start + series_skip,
+            dz,
+            delta_exponent + series_exponent,
+            dc,
+            derivative,
+            delta_exponent + series_exponent,
+            fractal == 1 ? 0. : 1.
+        );
+    }
+
+    // Value of the point `position` passes through the palette, before speed
+    // and offset, at full shade. The offset is left to Paint(), and whole
+    // passes dropped, so that values can be kept while the palette cycles.
+    vec4 PaletteValue(float position) {
+        float p = fract(position * palette_speed);
+        return vec4(p, p, 0., 1.);
+    }
+
+    // https://en.wikipedia.org/wiki/Plotting_algorithms_for_the_Mandelbrot_set
+    vec4 SmoothValue(int i, vec2 z) {
+        float log_zn = log(z.x * z.x + z.y * z.y) / 2.;
+        float nu = log2(log_zn / log(2.)) / log2(power);
+        float it = float(i) + 1. - nu;
+
+        return PaletteValue(it / PALETTE_PERIOD);
+    }
+
+    vec4 Paint(vec4 value) {
+        // Offset by half a texel so that filtering blends neighboring colors
+        // the way the palette does between its stops.
+        vec2 position = fract(palette_offset + value.xy) + 0.5 / PALETTE_SIZE;
+        vec3 color = mix(
+            texture(palette, vec2(position.x, 0.5)).rgb,
+            texture(palette, vec2(position.y, 0.5)).rgb,
+            value.z
+        );
+        return vec4(color * value.w, 1.);
+    }
+
+    // Longest cycle looked for inside the set.
+    const int MAX_PERIOD = 64;
+
+    // Period of the cycle the orbit of a point inside the set has been drawn
+    // into, with z the last point of that orbit, or 0 if none is found.
+    int Period(vec2 z, vec2 c) {
+        vec2 start = z;
+        for (int p = 1; p <= MAX_PERIOD; ++p) {
+            z = Step(z, c);
+            if (length(z - start) < 1e-4) return p;
+        }
+        return 0;
+    }
+
+    // Shade of a point c inside the set by its distance to the boundary, in
+    // [0, 1] from the boundary to a few pixels in, estimated from the
+    // attracting cycle of the given period through z.
+    // https://en.wikipedia.org/wiki/Plotting_algorithms_for_the_Mandelbrot_set#Interior_distance_estimation
+    //
+    // Julia sets only have the derivative with respect to z, so they are
+    // shaded by 1 - |dz|^2 instead, which goes to 0 as the cycle stops
+    // attracting near the boundary.
+    float InteriorShade(vec2 z, vec2 c, int period, float pixel) {
+        vec2 dz = vec2(1., 0.);
+        vec2 dc = vec2(0.);
+        vec2 dzdz = vec2(0.);
+        vec2 dcdz = vec2(0.);
+        for (int p = 0; p < MAX_PERIOD; ++p) {
+            if (p >= period) break;
+            dcdz = 2. * (cmul(z, dcdz) + cmul(dz, dc));
+            dzdz = 2. * (cmul(z, dzdz) + cmul(dz, dz));
+            dz = 2. * cmul(z, dz);
+            dc = 2. * cmul(z, dc) + vec2(1., 0.);
+            z = cmul(z, z) + c;
+        }
+        float attraction = 1. - dot(dz, dz);
+        if (fractal == 1) return attraction;
+
+        // dc / (1 - dz)
+        vec2 w = vec2(1., 0.) - dz;
+        vec2 ratio = vec2(dc.x * w.x + dc.y * w.y, dc.y * w.x - dc.x * w.y) / dot(w, w);
+        float distance = attraction / length(dcdz + cmul(dzdz, ratio));
+        return distance / pixel / 4.;
+    }
+
+    // Value of a point c inside the set, whose orbit ended at z.
+    vec4 InteriorValue(vec2 z, vec2 c, float pixel) {
+        if (interior == 1) return PaletteValue(length(z) / 2.);
+
+        // Black, as no shade at all.
+        int period = interior >= 2 ? Period(z, c) : 0;
+        if (period == 0) return vec4(0.);
+
+        // Each period gets a color of its own, as iterations do outside.
+        vec4 value = PaletteValue(float(period) / PALETTE_PERIOD);
+        if (interior == 3) {
+            value.w = sqrt(clamp(InteriorShade(z, c, period, pixel), 0., 1.));
+        }
+        return value;
+    }
+
+    // Value of the point `pixel`, given in the coordinates of gl_FragCoord,
+    // as painted by Paint().
+    vec4 Sample(vec2 pixel) {
+        vec3 m = Iterate(pixel / resolution * 2. - 1.);
+        vec2 z = vec2(m.x, m.y);
+        int i = int(m.z);
+        if (i == 0) {
+            return InteriorValue(z, fractal == 1 ? c : point, 2. * scale.x / resolution.x);
+        }
+
+        vec4 value = SmoothValue(i, z);
+        float t = trap / trap_width;
+        vec4 trapped = PaletteValue(t);
+        value = trap_shape == 4 ? vec4(trapped.x, value.x, clamp(t, 0., 1.), 1.) : trapped;
+        return value;
+    }
+
+    // https://nullprogram.com/blog/2018/07/31/
+    uint Hash(uint x) {
+        x ^= x >> 16;
+        x *= 0x7feb352du;
+        x ^= x >> 15;
+        x *= 0x846ca68bu;
+        x ^= x >> 16;
+        return x;
+    }
+
+    // A random point of the cell of the sample with the given index, each
+    // coordinate in [0, 1).
+    vec2 Jitter(vec2 pixel, int index) {
+        uvec2 p = uvec2(pixel);
+        uint h = Hash(p.x + Hash(p.y + Hash(uint(index))));
+        return vec2(h & 0xffffu, h >> 16) / 65536.;
+    }
+
+    // Averages samples by samples points of a grid over the pixel, each moved
+    // to a random point of its cell when jittered. A single sample stays at
+    // the center. Only the value of that sample is drawn when `values` is set,
+    // to be painted by the paint shader.
+    void main() {
+        vec2 pixel = floor(gl_FragCoord.xy);
+        if (values == 1) {
+            fragmentColor = Sample(pixel + 0.5);
+            return;
+        }
+
+        vec4 sum = vec4(0.);
+        for (int j = 0; j < MAX_SAMPLES; ++j) {
+            if (j >= samples) break;
+            for (int i = 0; i < MAX_SAMPLES; ++i) {
+                if (i >= samples) break;
+                vec2 offset = jitter == 1 && samples > 1 ? Jitter(pixel, j * samples + i) : vec2(0.5);
+                sum += Paint(Sample(pixel + (vec2(i, j) + offset) / float(samples)));
+            }
+        }
+        fragmentColor = sum / float(samples * samples);
+    }
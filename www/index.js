@@ -1,3 +1,42 @@
 import * as wasm from "julia-set-with-wasm";
 
-wasm.start();
+wasm
+  .start()
+  .then((explorer) => {
+    window.explorer = explorer;
+
+    // Downloads the current view, e.g. `savePng(3840, 2160)`.
+    window.savePng = (width = window.innerWidth, height = window.innerHeight) => {
+      const bytes = explorer.export_png(width, height);
+      const link = document.createElement("a");
+      link.href = URL.createObjectURL(new Blob([bytes], { type: "image/png" }));
+      link.download = "fractal.png";
+      link.click();
+      // Some browsers only start the download once the click has returned.
+      setTimeout(() => URL.revokeObjectURL(link.href));
+    };
+
+    // Dropping a .ugr, .ggr or .map gradient file onto the page uses it as the
+    // palette.
+    window.addEventListener("dragover", (event) => event.preventDefault());
+    window.addEventListener("drop", (event) => {
+      event.preventDefault();
+      const file = event.dataTransfer.files[0];
+      if (file) {
+        file
+          .text()
+          .then((text) => explorer.import_palette(file.name, text))
+          .catch((error) => console.error(error));
+      }
+    });
+  })
+  // `error.kind` tells why the explorer failed to start. See `src/error.rs`.
+  .catch((error) => {
+    const message = document.createElement("p");
+    message.textContent =
+      error.kind === "context"
+        ? "This page needs a canvas to draw on, which your browser does not provide."
+        : `The explorer failed to start: ${error.message}`;
+    document.body.prepend(message);
+    console.error(error);
+  });